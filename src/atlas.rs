@@ -1,10 +1,12 @@
 use std::path::Path;
 
-use anyhow::{Context, Result};
 use image::{GenericImageView, RgbaImage};
 
-use crate::index::{is_frameish, Index, IndexGlyph, BASELINE, FRAME_WIDTH};
+use crate::index::{
+    is_frameish, Index, IndexGlyph, LoadedGlyph, LoadedIndex, BASELINE, FRAME_WIDTH,
+};
 use crate::point::Point;
+use crate::Error;
 
 /// Each glyph has an invisible edge of `r=0xff g=0x00 b=0xff a=0x00` pixels along the right and
 /// bottom forming a sort of "frame".
@@ -37,19 +39,19 @@ pub struct Glyph {
 }
 
 impl Glyph {
-    fn x(&self) -> u32 {
+    pub fn x(&self) -> u32 {
         self.tl.x
     }
 
-    fn y(&self) -> u32 {
+    pub fn y(&self) -> u32 {
         self.tl.y
     }
 
-    fn w(&self) -> u32 {
+    pub fn w(&self) -> u32 {
         self.br.x.saturating_sub(self.tl.x).saturating_add(1)
     }
 
-    fn h(&self) -> u32 {
+    pub fn h(&self) -> u32 {
         self.br.y.saturating_sub(self.tl.y).saturating_add(1)
     }
 }
@@ -74,6 +76,10 @@ impl<'i> Atlas<'i> {
         self.rows.iter().map(|row| row.len()).sum()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|row| row.is_empty())
+    }
+
     pub fn row_count(&self) -> usize {
         self.rows.len()
     }
@@ -97,17 +103,22 @@ impl<'i> Atlas<'i> {
                 }
             }
 
-            point = Point { x: 0, y: point.y + 1 };
+            point = Point {
+                x: 0,
+                y: point.y + 1,
+            };
 
             if !row.is_empty() {
                 rows.push(row);
             }
         }
 
-        return Atlas { rows, buf };
+        Atlas { rows, buf }
     }
 
-    pub fn save_images<P: AsRef<Path>>(&self, outdir: P) -> Result<Index> {
+    /// An index of the glyphs found, with each glyph's path numbered in order like `000.png` and
+    /// where it is in the atlas.
+    pub fn to_index(&self) -> Index {
         let mut i = 0;
         let rows = self
             .rows
@@ -117,25 +128,65 @@ impl<'i> Atlas<'i> {
                     .map(|glyph| {
                         let path = format!("{i:03}.png");
                         i += 1;
-
-                        let filepath = outdir.as_ref().join(&path);
-
-                        self.buf
-                            .view(glyph.x(), glyph.y(), glyph.w(), glyph.h())
-                            .to_image()
-                            .save(&filepath)
-                            .with_context(|| format!("save {}", filepath.display()))?;
-
-                        Ok(IndexGlyph {
+                        IndexGlyph {
                             path,
                             descent: glyph.descent,
-                        })
+                            at: Some((glyph.tl.x, glyph.tl.y)),
+                            ..IndexGlyph::default()
+                        }
                     })
-                    .collect::<Result<Vec<_>>>()
+                    .collect()
             })
-            .collect::<Result<Vec<_>>>()?;
+            .collect();
+        Index {
+            rows,
+            size: Some(self.buf.dimensions()),
+        }
+    }
+
+    /// Copy each glyph out of the atlas, pairing it with the glyph at the same row and position in
+    /// `index`, which should come from `to_index()`.
+    pub fn load_images(&self, index: Index) -> LoadedIndex {
+        let rows = self
+            .rows
+            .iter()
+            .zip(index.rows)
+            .map(|(row, index_row)| {
+                row.iter()
+                    .zip(index_row)
+                    .map(|(glyph, index_glyph)| LoadedGlyph {
+                        glyph: index_glyph,
+                        image: self
+                            .buf
+                            .view(glyph.x(), glyph.y(), glyph.w(), glyph.h())
+                            .to_image(),
+                    })
+                    .collect()
+            })
+            .collect();
+        LoadedIndex { rows }
+    }
+
+    /// Save each glyph to the path of the glyph at the same row and position in `index`, which
+    /// should come from `to_index()`.
+    pub fn save_images<P: AsRef<Path>>(&self, outdir: P, index: &Index) -> Result<(), Error> {
+        self.rows
+            .iter()
+            .zip(index.rows.iter())
+            .flat_map(|(row, index_row)| row.iter().zip(index_row.iter()))
+            .try_for_each(|(glyph, index_glyph)| {
+                let filepath = outdir.as_ref().join(&index_glyph.path);
 
-        Ok(Index { rows })
+                self.buf
+                    .view(glyph.x(), glyph.y(), glyph.w(), glyph.h())
+                    .to_image()
+                    .save(&filepath)
+                    .map_err(|source| Error::ImageFile {
+                        action: "save",
+                        path: filepath,
+                        source,
+                    })
+            })
     }
 }
 
@@ -187,3 +238,132 @@ impl Glyph {
         Ok(Glyph { tl, br, descent })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{copy_glyph_to_atlas, FRAME};
+    use crate::manifest::IndexFormat;
+    use image::Rgba;
+
+    /// An atlas of `size` with a glyph of `w`x`h` at each `x`,`y` and its baseline marker
+    /// `descent` up from the bottom. Glyph pixels are all sorts of colors and alphas but never
+    /// the frame's.
+    fn synthetic(size: (u32, u32), glyphs: &[(u32, u32, u32, u32, u32)]) -> RgbaImage {
+        let mut buf = RgbaImage::from_pixel(size.0, size.1, FRAME);
+        for (i, &(x, y, w, h, descent)) in glyphs.iter().enumerate() {
+            let image = RgbaImage::from_fn(w, h, |gx, gy| {
+                let n = (i as u32 * 31 + gx * 7 + gy * 13) as u8;
+                Rgba([n % 200, n.wrapping_mul(3) % 200, n / 2, n.wrapping_mul(5)])
+            });
+            copy_glyph_to_atlas(&mut buf, Point { x, y }, &image, descent);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let atlases = [
+            /* laid out in rows like pack does, with frame left over on the right and bottom */
+            synthetic(
+                (40, 30),
+                &[
+                    (0, 0, 5, 8, 2),
+                    (6, 0, 3, 8, 0),
+                    (10, 0, 7, 6, 6),
+                    (0, 9, 9, 4, 1),
+                    (10, 9, 1, 1, 0),
+                ],
+            ),
+            /* empty space above and between rows, rows that don't line up, tiny glyphs, and
+             * glyphs with their frame on the very last column and row */
+            synthetic(
+                (33, 41),
+                &[
+                    (2, 3, 4, 5, 5),
+                    (9, 4, 1, 7, 1),
+                    (25, 0, 7, 3, 0),
+                    (0, 20, 1, 1, 1),
+                    (4, 18, 11, 9, 3),
+                    (16, 22, 16, 6, 2),
+                    (30, 36, 2, 4, 0),
+                    (0, 38, 3, 2, 1),
+                ],
+            ),
+            /* a row with nothing in it between two that have glyphs */
+            synthetic(
+                (20, 32),
+                &[(0, 0, 4, 6, 1), (5, 0, 6, 6, 0), (3, 22, 5, 9, 2)],
+            ),
+            /* no glyphs at all */
+            synthetic((9, 7), &[]),
+        ];
+
+        for (a, original) in atlases.iter().enumerate() {
+            let atlas = Atlas::from_image(original);
+            for format in IndexFormat::DETECT_ORDER {
+                let index = atlas.to_index().serialize(format).unwrap();
+                let index = Index::deserialize(&index, format).unwrap();
+                let size = index.size.unwrap();
+                let loaded = atlas.load_images(index);
+                let layout = loaded.kept_layout(size).unwrap();
+                assert_eq!(loaded.clipped(&layout), [], "{a} {format}");
+                let packed = loaded.draw(&layout);
+                assert_eq!(packed.dimensions(), original.dimensions(), "{a} {format}");
+                assert!(
+                    packed == *original,
+                    "atlas {a} through {format} isn't the same"
+                );
+            }
+        }
+
+        /* the first one is laid out the same as packing without keeping the layout would */
+        let atlas = Atlas::from_image(&atlases[0]);
+        let packed = atlas
+            .load_images(atlas.to_index())
+            .to_atlas_image((40, 30))
+            .unwrap();
+        assert!(packed == atlases[0]);
+    }
+
+    #[test]
+    fn test_round_trip_bytes() {
+        const TGA: &[u8] = include_bytes!("../test.tga");
+        let original = crate::read_tga(TGA).unwrap();
+        let atlas = Atlas::from_image(&original);
+        let index = atlas.to_index();
+        let size = index.size.unwrap();
+        let loaded = atlas.load_images(index);
+        let packed = loaded.draw(&loaded.kept_layout(size).unwrap());
+        assert!(crate::write_tga(&packed).unwrap() == TGA);
+    }
+
+    #[test]
+    fn test_patch_keep_layout() {
+        let before = synthetic((20, 12), &[(0, 0, 4, 5, 1), (5, 0, 3, 5, 0)]);
+        /* the second glyph moved and the atlas grew since the index was written */
+        let after = synthetic((24, 14), &[(0, 0, 4, 5, 1), (9, 3, 3, 5, 2)]);
+        let old = Atlas::from_image(&before).to_index();
+        let unpacked = crate::unpack(&after);
+        let new = unpacked.index;
+
+        let mut patched = old.clone();
+        assert_eq!(patched.patch(&new), 2);
+        let (matched, html) = new.patch_html(&old.to_html()).unwrap();
+        assert_eq!(matched, 2);
+        /* and again, over the size it was given */
+        let (_, again) = new.patch_html(&html).unwrap();
+        for patched in [
+            patched,
+            Index::from_html(&html).unwrap(),
+            Index::from_html(&again).unwrap(),
+        ] {
+            assert_eq!(patched.size, Some((24, 14)));
+            let loaded = patched
+                .with_images(|path| unpacked.images.get(path).cloned())
+                .unwrap();
+            let packed = loaded.draw(&loaded.kept_layout((24, 14)).unwrap());
+            assert!(packed == after);
+        }
+    }
+}
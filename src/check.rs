@@ -0,0 +1,226 @@
+use std::fmt;
+
+use image::{Rgba, RgbaImage};
+
+use crate::atlas::{Atlas, Glyph};
+use crate::index::{is_frameish, LoadedIndex, BASELINE, FRAME, FRAME_WIDTH};
+use crate::layout;
+use crate::merge;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn error(message: String) -> Self {
+        Problem {
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    pub fn warning(message: String) -> Self {
+        Problem {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+pub fn count_errors(problems: &[Problem]) -> usize {
+    problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .count()
+}
+
+/// Look for things in an atlas that the game, or `Atlas::from_image`, gets wrong. `atlas` should
+/// be from `buf`.
+pub fn check_atlas(buf: &RgbaImage, atlas: &Atlas) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    if atlas.rows.is_empty() {
+        problems.push(Problem::error("no glyphs found".to_owned()));
+    }
+
+    check_edges(buf, &mut problems);
+    check_frame_alpha(buf, &mut problems);
+
+    for (r, row) in atlas.rows.iter().enumerate() {
+        for (c, glyph) in row.iter().enumerate() {
+            let at = format!("row {r} glyph {c} at {},{}", glyph.tl.x, glyph.tl.y);
+
+            if let Some((x, y)) =
+                glyph_pixels(glyph).find(|&(x, y)| is_frameish(*buf.get_pixel(x, y)))
+            {
+                problems.push(Problem::error(format!(
+                    "{at}: frame colored pixel inside the glyph at {x},{y}"
+                )));
+            }
+
+            let edge_x = glyph.br.x + FRAME_WIDTH;
+            let cyans = (glyph.tl.y..=glyph.br.y)
+                .filter(|&y| buf.get_pixel_checked(edge_x, y) == Some(&BASELINE))
+                .count();
+            if cyans > 1 {
+                problems.push(Problem::error(format!(
+                    "{at}: {cyans} baseline pixels in the right edge, only the top one is used"
+                )));
+            }
+        }
+
+        let heights = row
+            .iter()
+            .map(|glyph| glyph.br.y - glyph.tl.y + 1)
+            .collect::<Vec<_>>();
+        if let (Some(min), Some(max)) = (heights.iter().min(), heights.iter().max()) {
+            if min != max {
+                problems.push(Problem::warning(format!(
+                    "row {r}: glyphs are between {min} and {max} pixels tall"
+                )));
+            }
+        }
+    }
+
+    let lengths = atlas.rows.iter().map(Vec::len).collect::<Vec<_>>();
+    problems.extend(check_row_lengths(&lengths));
+    problems
+}
+
+/// Problems with a directory that won't show up in the packed atlas because packing hides them.
+pub fn check_loaded(loaded: &LoadedIndex) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for (r, row) in loaded.rows.iter().enumerate() {
+        for loaded in row {
+            if let Some(conflict) = loaded.glyph.meta.get(merge::CONFLICT) {
+                problems.push(Problem::error(format!(
+                    "row {r} {}: unresolved merge conflict, {conflict}",
+                    loaded.glyph.path
+                )));
+            }
+            let height = loaded.image.height();
+            if loaded.glyph.descent > height {
+                problems.push(Problem::error(format!(
+                    "row {r} {}: descent {} is more than the glyph's height {height}",
+                    loaded.glyph.path, loaded.glyph.descent
+                )));
+            }
+        }
+    }
+    problems
+}
+
+fn glyph_pixels(glyph: &Glyph) -> impl Iterator<Item = (u32, u32)> + '_ {
+    (glyph.tl.y..=glyph.br.y).flat_map(|y| (glyph.tl.x..=glyph.br.x).map(move |x| (x, y)))
+}
+
+/// Glyphs are framed on the right and bottom, so a glyph with pixels in the last column or row of
+/// the image has no frame there and isn't found at all.
+fn check_edges(buf: &RgbaImage, problems: &mut Vec<Problem>) {
+    let (width, height) = buf.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    let visible = |&(x, y): &(u32, u32)| !is_frameish(*buf.get_pixel(x, y));
+    let right = (0..height).map(|y| (width - 1, y)).find(visible);
+    let bottom = (0..width).map(|x| (x, height - 1)).find(visible);
+    for (edge, found) in [("right", right), ("bottom", bottom)] {
+        if let Some((x, y)) = found {
+            problems.push(Problem::error(format!(
+                "glyph touches the {edge} edge of the image at {x},{y}, it has no frame there"
+            )));
+        }
+    }
+}
+
+/// Pixels that are the frame color but not invisible are drawn as part of a glyph.
+fn check_frame_alpha(buf: &RgbaImage, problems: &mut Vec<Problem>) {
+    let rgb = |Rgba([r, g, b, _]): Rgba<u8>| [r, g, b];
+    let mut found = buf
+        .enumerate_pixels()
+        .filter(|(_, _, &pixel)| {
+            pixel[3] != 0 && (rgb(pixel) == rgb(FRAME) || rgb(pixel) == rgb(BASELINE))
+        })
+        .map(|(x, y, pixel)| (x, y, pixel[3]));
+    if let Some((x, y, alpha)) = found.next() {
+        problems.push(Problem::error(format!(
+            "frame colored pixel at {x},{y} has alpha {alpha} instead of 0, and {} more",
+            found.count()
+        )));
+    }
+}
+
+/// Compare the number of glyphs in each row to the text rows of the stock atlas.
+pub fn check_row_lengths(lengths: &[usize]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let text_rows = layout::text_row_lengths();
+    for (r, (found, len)) in lengths.iter().zip(&text_rows).enumerate() {
+        if found != len {
+            problems.push(Problem::warning(format!(
+                "row {r}: {found} glyphs, the text rows of the stock atlas have {len}"
+            )));
+        }
+    }
+    if lengths.len() < text_rows.len() {
+        problems.push(Problem::warning(format!(
+            "only {} rows, the stock atlas has {} rows of text before the icons",
+            lengths.len(),
+            text_rows.len()
+        )));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_atlas() {
+        let mut buf = RgbaImage::from_pixel(8, 5, FRAME);
+        /* a 3x3 glyph with two baseline pixels and a stray frame pixel */
+        for (x, y) in (0..3).flat_map(|y| (0..3).map(move |x| (x, y))) {
+            if (x, y) != (1, 1) {
+                buf.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+            }
+        }
+        buf.put_pixel(3, 0, BASELINE);
+        buf.put_pixel(3, 2, BASELINE);
+        /* a glyph against the right edge */
+        buf.put_pixel(7, 1, Rgba([255, 255, 255, 255]));
+        /* visible pink */
+        buf.put_pixel(5, 4, Rgba([255, 0, 255, 255]));
+
+        let atlas = Atlas::from_image(&buf);
+        let problems = check_atlas(&buf, &atlas);
+        let errors = problems
+            .iter()
+            .filter(|problem| problem.severity == Severity::Error)
+            .map(|problem| problem.message.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(errors.len(), 5, "{errors:#?}");
+        assert!(errors[0].contains("right edge of the image"));
+        assert!(errors[1].contains("bottom edge of the image"));
+        assert!(errors[2].contains("alpha 255"));
+        assert!(errors[3].contains("inside the glyph at 1,1"));
+        assert!(errors[4].contains("2 baseline pixels"));
+    }
+}
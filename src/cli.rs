@@ -0,0 +1,627 @@
+use clap::{Args, Parser, Subcommand};
+use image::Rgba;
+
+use atlast::effects::Effects;
+use atlast::manifest::IndexFormat;
+use atlast::scale::Filter;
+
+use crate::report::MessageFormat;
+
+/// Tools for packing and unpacking GameFont.tga from Civilization IV.
+#[derive(Debug, Parser)]
+#[command(name = "atlast", version, after_long_help = EXAMPLES)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+    /// Print progress and errors as human or json lines, for other programs to read
+    #[arg(
+        long,
+        global = true,
+        value_name = "FORMAT",
+        value_parser = message_format,
+        default_value = "human"
+    )]
+    pub message_format: MessageFormat,
+}
+
+/// Whether `--message-format json` is in `argv`, for when it couldn't be parsed.
+pub fn wants_json(argv: &[String]) -> bool {
+    argv.windows(2)
+        .any(|args| args[0] == "--message-format" && args[1] == "json")
+        || argv.iter().any(|arg| arg == "--message-format=json")
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Unpack GameFont.tga to a directory of .png files and an index
+    Unpack(UnpackArgs),
+    /// Opposite of unpack, write GameFont.tga using unpacked files
+    Pack(PackArgs),
+    /// Like pack, but also write GameFont_75.tga with glyphs scaled to 75%
+    PackPair(PackPairArgs),
+    /// Resample every glyph in a directory, writing a new directory
+    Scale(ScaleArgs),
+    /// Rasterize the text portion of the atlas from a .ttf/.otf
+    RenderText(RenderTextArgs),
+    /// Look for problems in a .tga or directory, exits non-zero on errors
+    Check(CheckArgs),
+    /// Print the ID the game gives each glyph, from a directory or .tga
+    Ids(IdsArgs),
+    /// List glyphs inserted, removed, moved, resized, or changed between two versions of an atlas
+    Diff(DiffArgs),
+    /// Combine the changes two versions made to a common base into a new directory, exits
+    /// non-zero if any glyphs conflict
+    Merge(MergeArgs),
+    /// Build a directory out of rows of other atlases
+    Compose(ComposeArgs),
+    /// Save one glyph of a .tga as a .png
+    Extract(ExtractArgs),
+    /// Draw a .png over one glyph of a .tga and write the .tga back
+    Replace(ReplaceArgs),
+    /// Show a directory and the atlas it packs to in a browser, reloading as files are saved
+    Serve(ServeArgs),
+    /// Draw a line of game text with the glyphs of a .tga or directory, to a .png
+    Simulate(SimulateArgs),
+}
+
+/// The commands that used to be flags, like `--unpack`.
+pub const COMMANDS: [&str; 12] = [
+    "unpack",
+    "pack",
+    "pack-pair",
+    "scale",
+    "render-text",
+    "check",
+    "ids",
+    "diff",
+    "merge",
+    "compose",
+    "extract",
+    "replace",
+];
+
+#[derive(Debug, Args)]
+pub struct OutputArgs {
+    /// Where to write, instead of a name based on the input
+    #[arg(short, long)]
+    pub output: Option<String>,
+    /// Read but don't write files
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct UnpackArgs {
+    #[arg(default_value = "GameFont.tga")]
+    pub tga: String,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Do not write an index
+    #[arg(long)]
+    pub skip_index: bool,
+    /// Only update matching images in an existing index.html
+    #[arg(long, conflicts_with = "skip_index")]
+    pub patch_index: bool,
+    /// Don't name glyphs after the stock BtS layout
+    #[arg(long)]
+    pub numbered: bool,
+    /// Write the index as html, json, or toml; json and toml also get a preview.html
+    #[arg(long, value_name = "FORMAT", value_parser = index_format, default_value = "html")]
+    pub index_format: IndexFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct PackArgs {
+    #[arg(default_value = "GameFont")]
+    pub dir: String,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// The .tga's dimensions, either can be left out for just big enough
+    #[arg(long, value_name = "[WIDTH]x[HEIGHT]", value_parser = dims)]
+    pub size: Option<(u32, u32)>,
+    /// Put glyphs back where they were when unpacked, and use the size it was unpacked from
+    /// unless --size is given
+    #[arg(long, overrides_with = "no_keep_layout")]
+    keep_layout: bool,
+    /// Flow glyphs into rows even if atlast.toml says to keep the layout
+    #[arg(long, overrides_with = "keep_layout")]
+    no_keep_layout: bool,
+    /// Break rows wider than the --size width onto new rows instead of cutting glyphs off
+    #[arg(long, overrides_with = "no_wrap")]
+    wrap: bool,
+    /// Don't wrap rows even if atlast.toml says to
+    #[arg(long, overrides_with = "wrap")]
+    no_wrap: bool,
+    /// Write the atlas even if some glyphs run off its edges, instead of failing
+    #[arg(long, overrides_with = "no_allow_clip")]
+    allow_clip: bool,
+    /// Fail when glyphs run off the edges even if atlast.toml allows it
+    #[arg(long, overrides_with = "allow_clip")]
+    no_allow_clip: bool,
+    /// Check FontButtonIndex and icon counts in the mod's XML against the atlas first and don't
+    /// write anything if they don't line up
+    #[arg(long, value_name = "Assets")]
+    pub check_xml: Option<String>,
+    /// Keep running and pack again whenever a glyph image or the index changes
+    #[arg(long)]
+    pub watch: bool,
+    #[command(flatten)]
+    pub effects: EffectsArgs,
+}
+
+impl PackArgs {
+    /// `--keep-layout` or `--no-keep-layout`, whichever came last, or neither.
+    pub fn keep_layout(&self) -> Option<bool> {
+        flag(self.keep_layout, self.no_keep_layout)
+    }
+
+    pub fn wrap(&self) -> Option<bool> {
+        flag(self.wrap, self.no_wrap)
+    }
+
+    pub fn allow_clip(&self) -> Option<bool> {
+        flag(self.allow_clip, self.no_allow_clip)
+    }
+}
+
+/// A flag with a `--no-` counterpart, which override each other.
+fn flag(yes: bool, no: bool) -> Option<bool> {
+    match (yes, no) {
+        (false, false) => None,
+        (yes, _) => Some(yes),
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct PackPairArgs {
+    #[command(flatten)]
+    pub pack: PackArgs,
+    /// GameFont_75.tga's dimensions, by default 75% of the full size atlas or bigger if the
+    /// glyphs don't fit
+    #[arg(long, value_name = "[WIDTH]x[HEIGHT]", value_parser = dims)]
+    pub size_75: Option<(u32, u32)>,
+    /// Resample with lanczos (default), box, or nearest
+    #[arg(long, value_parser = filter)]
+    pub filter: Option<Filter>,
+}
+
+#[derive(Debug, Args)]
+pub struct ScaleArgs {
+    /// Like 0.75
+    #[arg(value_parser = factor)]
+    pub factor: f32,
+    #[arg(default_value = "GameFont")]
+    pub dir: String,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Resample with lanczos (default), box, or nearest
+    #[arg(long, value_parser = filter, default_value = "lanczos")]
+    pub filter: Filter,
+}
+
+#[derive(Debug, Args)]
+pub struct RenderTextArgs {
+    pub font: String,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Font size in pixels
+    #[arg(long, value_name = "PIXELS", value_parser = factor, default_value = "18")]
+    pub size: f32,
+    /// Characters to render, one row per line
+    #[arg(long, value_name = "FILE")]
+    pub text: Option<String>,
+    /// Text color
+    #[arg(long, value_name = "RRGGBB[AA]", value_parser = color, default_value = "ffffff")]
+    pub color: Rgba<u8>,
+    /// Write the index as html, json, or toml
+    #[arg(long, value_name = "FORMAT", value_parser = index_format, default_value = "html")]
+    pub index_format: IndexFormat,
+    #[command(flatten)]
+    pub effects: EffectsArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct EffectsArgs {
+    /// Outline glyphs this thick
+    #[arg(long, value_name = "THICKNESS", default_value = "0")]
+    pub outline: f32,
+    #[arg(long, value_name = "RRGGBB[AA]", value_parser = color, default_value = "212121cc")]
+    pub outline_color: Rgba<u8>,
+    /// Pad glyphs by this times the outline thickness
+    #[arg(long, value_name = "FACTOR", default_value = "0.5")]
+    pub outline_factor: f32,
+    /// Drop shadow offset
+    #[arg(long, value_name = "X,Y", value_parser = offset, default_value = "0,0")]
+    pub shadow: (i32, i32),
+    /// Shadow blur, like canvas shadowBlur
+    #[arg(long, value_name = "BLUR", default_value = "0")]
+    pub shadow_blur: f32,
+    #[arg(long, value_name = "RRGGBB[AA]", value_parser = color, default_value = "212121cc")]
+    pub shadow_color: Rgba<u8>,
+}
+
+impl From<&EffectsArgs> for Effects {
+    fn from(args: &EffectsArgs) -> Self {
+        Effects {
+            shadow_color: args.shadow_color,
+            shadow_x: args.shadow.0,
+            shadow_y: args.shadow.1,
+            shadow_blur: args.shadow_blur,
+            outline_color: args.outline_color,
+            outline_thickness: args.outline,
+            outline_factor: args.outline_factor,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct CheckArgs {
+    /// A .tga, directory, or manifest file
+    #[arg(default_value = "GameFont.tga")]
+    pub input: String,
+}
+
+#[derive(Debug, Args)]
+pub struct IdsArgs {
+    /// A .tga, directory, or manifest file
+    #[arg(default_value = "GameFont")]
+    pub input: String,
+    /// Flag glyphs whose ID differs from this older version
+    #[arg(long, value_name = "PATH")]
+    pub previous: Option<String>,
+    /// Don't name glyphs after the stock BtS layout
+    #[arg(long)]
+    pub numbered: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// Each a .tga, directory, or manifest file
+    pub old: String,
+    pub new: String,
+    /// Also write an html page of the changes side by side
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<String>,
+    /// Don't write the report
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+    /// Don't name glyphs after the stock BtS layout
+    #[arg(long)]
+    pub numbered: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MergeArgs {
+    pub base: String,
+    pub ours: String,
+    pub theirs: String,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Write the index as html, json, or toml
+    #[arg(long, value_name = "FORMAT", value_parser = index_format, default_value = "html")]
+    pub index_format: IndexFormat,
+    /// Don't name glyphs after the stock BtS layout
+    #[arg(long)]
+    pub numbered: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ComposeArgs {
+    /// Like `ModB@4-7`, see the examples
+    #[arg(value_name = "SOURCE@ROWS", required = true)]
+    pub selectors: Vec<String>,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Write the index as html, json, or toml
+    #[arg(long, value_name = "FORMAT", value_parser = index_format, default_value = "html")]
+    pub index_format: IndexFormat,
+    /// Don't name glyphs after the stock BtS layout
+    #[arg(long)]
+    pub numbered: bool,
+    /// Check FontButtonIndex and icon counts in the mod's XML against the result first
+    #[arg(long, value_name = "Assets")]
+    pub check_xml: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ExtractArgs {
+    pub tga: String,
+    /// Which glyph, counting from 0
+    #[arg(long, value_name = "ROW,GLYPH", value_parser = slot)]
+    pub glyph: (usize, usize),
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Don't name glyphs after the stock BtS layout
+    #[arg(long)]
+    pub numbered: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplaceArgs {
+    pub tga: String,
+    pub png: String,
+    /// Which glyph, counting from 0
+    #[arg(long, value_name = "ROW,GLYPH", value_parser = slot)]
+    pub glyph: (usize, usize),
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    #[arg(default_value = "GameFont")]
+    pub dir: String,
+    /// Listen on http://127.0.0.1:PORT/
+    #[arg(long, default_value = "8000")]
+    pub port: u16,
+}
+
+#[derive(Debug, Args)]
+pub struct SimulateArgs {
+    /// A .tga, directory, or manifest file
+    pub input: String,
+    /// Like "Hello [ICON_GOLD] world", with [NEWLINE] or line breaks for more lines
+    pub text: String,
+    #[command(flatten)]
+    pub output: OutputArgs,
+    /// Find [ICON_...] where the game would with the mod's XML, instead of Beyond the Sword's
+    #[arg(long, value_name = "Assets")]
+    pub xml: Option<String>,
+    /// How far a space moves along
+    #[arg(long, value_name = "PIXELS", default_value = "4")]
+    pub space: u32,
+    #[arg(long, value_name = "RRGGBB[AA]", value_parser = color, default_value = "000000")]
+    pub background: Rgba<u8>,
+    /// Color the row each line of text sits on
+    #[arg(long, value_name = "RRGGBB[AA]", value_parser = color)]
+    pub guide: Option<Rgba<u8>>,
+}
+
+fn index_format(s: &str) -> Result<IndexFormat, String> {
+    IndexFormat::from_name(s).ok_or_else(|| "expected html, json, or toml".to_owned())
+}
+
+fn message_format(s: &str) -> Result<MessageFormat, String> {
+    MessageFormat::from_name(s).ok_or_else(|| "expected human or json".to_owned())
+}
+
+fn filter(s: &str) -> Result<Filter, String> {
+    Filter::from_name(s).ok_or_else(|| "expected lanczos, box, or nearest".to_owned())
+}
+
+fn dims(s: &str) -> Result<(u32, u32), String> {
+    parse_dims(s).ok_or_else(|| "expected [WIDTH]x[HEIGHT], like 2046x540".to_owned())
+}
+
+fn factor(s: &str) -> Result<f32, String> {
+    s.parse()
+        .ok()
+        .filter(|&factor: &f32| factor > 0. && factor.is_finite())
+        .ok_or_else(|| "expected a number greater than zero".to_owned())
+}
+
+fn color(s: &str) -> Result<Rgba<u8>, String> {
+    parse_color(s).ok_or_else(|| "expected RRGGBB or RRGGBBAA".to_owned())
+}
+
+fn offset(s: &str) -> Result<(i32, i32), String> {
+    parse_offset(s).ok_or_else(|| "expected X,Y".to_owned())
+}
+
+fn slot(s: &str) -> Result<(usize, usize), String> {
+    parse_slot(s).ok_or_else(|| "expected ROW,GLYPH".to_owned())
+}
+
+pub fn parse_color(s: &str) -> Option<Rgba<u8>> {
+    let s = s.strip_prefix('#').unwrap_or(s);
+    let channel = |i: usize| u8::from_str_radix(s.get(i..i + 2)?, 16).ok();
+    match s.len() {
+        6 => Some(Rgba([channel(0)?, channel(2)?, channel(4)?, 255])),
+        8 => Some(Rgba([channel(0)?, channel(2)?, channel(4)?, channel(6)?])),
+        _ => None,
+    }
+}
+
+pub fn parse_offset(s: &str) -> Option<(i32, i32)> {
+    let (x, y) = s.split_once(',')?;
+    Some((x.parse().ok()?, y.parse().ok()?))
+}
+
+pub fn parse_slot(s: &str) -> Option<(usize, usize)> {
+    let (row, col) = s.split_once(',')?;
+    Some((row.trim().parse().ok()?, col.trim().parse().ok()?))
+}
+
+pub fn parse_dims(s: &str) -> Option<(u32, u32)> {
+    let (w, h) = s.split_once('x')?;
+    Some((parse_dim(w)?, parse_dim(h)?))
+}
+
+fn parse_dim(s: &str) -> Option<u32> {
+    match s {
+        "" => Some(0),
+        _ => s.parse().ok(),
+    }
+}
+
+const EXAMPLES: &str = r#"examples:
+
+  atlast unpack
+    Read `GameFont.tga` and write each glyph as a .png file in the
+    `GameFont` directory with an `index.html` needed for repacking.
+
+  atlast unpack GameFont_75.tga
+    Unpack `GameFont_75.tga` to the `GameFont_75` directory.
+
+    If the rows look like the stock Beyond the Sword GameFont.tga, icons are
+    saved with names like `yield_food.png` and labelled in the index, and text
+    glyphs are labelled with their character. Otherwise, or with --numbered,
+    images are named by their order in the atlas like `057.png`.
+
+  atlast unpack SpecialGameFont.tga --output GameFont_75 --patch-index
+    Unpack `SpecialGameFont.tga` to the `GameFont_75` directory. Instead of overwriting
+    `GameFont_75/index.html`, only update `<img>` elements with paths that match the image files
+    unpacked from the .tga file. This could be useful if you're unpacking a .tga that contains just
+    the text portion of the atlas and want to update just the descent/baseline markers for those
+    images in the html file.
+
+  atlast pack --output SexyLettuce.tga
+    Read the `index.html` in the `GameFont` directory and pack the
+    images listed there into an atlas named `SexyLettuce.tga`.
+
+    Defaults for pack and pack-pair can be kept in an `atlast.toml` in the
+    directory, like `size = "2046x540"` and `output = "../GameFont.tga"`,
+    with paths relative to it. Options given on the command line win.
+
+  atlast pack --keep-layout
+    Pack `GameFont` with each glyph where it was in the .tga it was unpacked
+    from, so an atlas that wasn't changed packs to the same pixels. Glyphs that
+    are new, or that grew and now overlap something, are placed after the glyph
+    before them in their row instead.
+
+  atlast pack --size 1024x1024 --wrap
+    Pack `GameFont` into a 1024x1024 atlas, moving the end of any row that
+    is too wide onto a new row under it. Glyphs stay in the same order, but
+    icons after a wrapped icon row get different game IDs, which is warned
+    about.
+
+  atlast pack --watch
+    Pack `GameFont`, then keep running and pack it again whenever one of its
+    images or its index is saved, reading only the files that changed.
+
+  atlast serve GameFont --port 8000
+    Show the `GameFont` listing and the atlas it packs to on
+    http://127.0.0.1:8000/, with a checkbox to see the frame and baseline
+    pixels. The page reloads whenever an image or the index is saved.
+    Glyphs can be dragged to reorder them, clicked to set their baseline,
+    or replaced by dropping an image on them, saving each change.
+
+  atlast simulate GameFont "Hello [ICON_GOLD] world" --guide 00ffff80
+    Draw the text with the glyphs in `GameFont` the way the game lays it out,
+    to `simulated.png`, with each line's baseline colored in behind it.
+
+  atlast pack-pair GameFont --size 2046x540 --size-75 1535x405
+    Pack `GameFont.tga` and `GameFont_75.tga` from the same `GameFont`
+    directory. For the second, every glyph is resampled to 75% and its
+    baseline marker moved to match. Neither is written if either fails.
+
+  atlast scale 0.75 GameFont --output GameFont_75 --filter box
+    Write every glyph in `GameFont` scaled to 75% to `GameFont_75`, with a
+    manifest in the same format and baseline markers moved to match. The
+    output directory defaults to the input's name and the percentage.
+
+  atlast render-text Quicksand.ttf --size 18 --output GameFont-text.tga
+    Like atlast.html, draw the 181 printable characters from the font to an
+    atlas that can be unpacked over a GameFont directory with --patch-index.
+    If --output isn't a .tga file, write images and an `index.html` to that
+    directory instead.
+
+  atlast pack --outline 2 --shadow 1,1
+    Pack `GameFont` like usual, but give every glyph a 2px thick outline
+    and a drop shadow. Glyphs grow to fit and their baseline markers are
+    moved to match.
+
+  atlast unpack --index-format toml
+    Like the first example, but write `GameFont/index.toml` as the manifest.
+    pack finds whichever of index.toml, index.json, or index.html is in the
+    directory, in that order.
+
+  atlast pack --dry-run --check-xml MyMod/Assets
+    Check that the religion, corporation, and bonus rows of `GameFont` start
+    where the game expects for the number of each in the mod's XML, and that
+    every bonus's FontButtonIndex is a glyph in the bonus row. Without
+    --dry-run, `GameFont.tga` is only written if there are no errors.
+
+  atlast check GameFont.tga
+    Report glyphs touching the edge of the image, frame colored pixels inside
+    glyphs or visible ones anywhere, more than one baseline marker per glyph,
+    rows of glyphs with different heights, and text rows that don't have as
+    many glyphs as the stock atlas. A directory is checked by packing it, and
+    also for glyphs with a descent taller than their image.
+
+  atlast ids GameFont --previous GameFont.tga
+    Print the ID the game gives each glyph in `GameFont`, like 8483 for
+    [ICON_FOOD] or the FontButtonIndex of a bonus relative to the first bonus.
+    Glyphs whose ID isn't the same as in `GameFont.tga` are flagged. Glyphs are
+    matched by name, then character, then path.
+
+  atlast diff GameFont.tga GameFont --report diff.html
+    List how the glyphs in `GameFont` differ from `GameFont.tga`. Glyphs are
+    matched by name or character, or with neither by having the same pixels
+    and then by position, so an inserted glyph shows up as one insertion and
+    not every glyph after it changing. `diff.html` shows the old and new image
+    of each glyph that changed.
+
+  atlast extract GameFont.tga --glyph 8,12 -o icon.png
+  atlast replace GameFont.tga icon.png --glyph 8,12
+    Save the 13th glyph of the 9th row to `icon.png`, and after editing it,
+    put it back without unpacking the rest. If the new image is a different
+    size, the glyphs after it in the row are moved over, or if that doesn't
+    fit, the whole atlas is packed again at the same size.
+
+  atlast compose ModA.tga@0-3 ModB@4-7 +Extras@0 ModB@8 --output GameFont
+    Write a directory with the text rows of `ModA.tga`, the first four icon
+    rows of the `ModB` directory with the glyphs from the first row of `Extras`
+    added to the end of the last of them, then the last row of `ModB`. Each
+    argument is `SOURCE@ROWS:GLYPHS`, where ROWS and GLYPHS are like `3`,
+    `3-5`, or `3-` for 3 to the end, and GLYPHS picks from every row selected.
+    Without `@ROWS`, the whole source is used, and a leading `+` appends to the
+    previous row. Nothing is written if the rows no longer start at the IDs
+    the game expects, which --check-xml checks against the mod's XML.
+
+  atlast merge GameFont-base GameFont-mine GameFont-yours --output GameFont
+    Apply the changes both `GameFont-mine` and `GameFont-yours` made to
+    `GameFont-base`, writing the result to `GameFont`. Glyphs inserted, removed,
+    moved, or replaced by one side are taken from that side. Glyphs both sides
+    changed differently keep the first side's version with the other saved
+    next to it as `*.theirs.png`, and are marked with a `data-conflict`
+    attribute in the index, which check reports as an error.
+
+The index.html is used as a manifest for repacking GameFont.tga and contains information about
+descent/baseline markers.
+
+exit codes:
+  1  anything else
+  2  bad arguments, like a --glyph that isn't in the atlas, or a bad atlast.toml
+  3  a file couldn't be read or written
+  4  an index.html, index.json, or index.toml couldn't be parsed
+  5  a .tga or glyph image couldn't be decoded
+  6  glyphs don't fit in the atlas
+  7  check, check-xml, or compose found errors, or merge left conflicts
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["atlast", "pack-pair", "Mod", "--size", "x540", "-n"]);
+        let Command::PackPair(args) = cli.unwrap().command else {
+            panic!("not pack-pair");
+        };
+        assert_eq!(args.pack.dir, "Mod");
+        assert_eq!(args.pack.size, Some((0, 540)));
+        assert!(args.pack.output.dry_run);
+        assert_eq!(args.pack.keep_layout(), None);
+
+        let cli = Cli::try_parse_from(["atlast", "pack", "--wrap", "--no-wrap", "--no-allow-clip"]);
+        let Command::Pack(args) = cli.unwrap().command else {
+            panic!("not pack");
+        };
+        assert_eq!(args.wrap(), Some(false));
+        assert_eq!(args.allow_clip(), Some(false));
+        let cli = Cli::try_parse_from(["atlast", "pack", "--no-keep-layout", "--keep-layout"]);
+        let Command::Pack(args) = cli.unwrap().command else {
+            panic!("not pack");
+        };
+        assert_eq!(args.keep_layout(), Some(true));
+
+        /* no more than one input, instead of the last one winning */
+        assert!(Cli::try_parse_from(["atlast", "unpack", "a.tga", "b.tga"]).is_err());
+        assert!(Cli::try_parse_from(["atlast", "pack", "--size", "big"]).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::index::{LoadedGlyph, LoadedIndex};
+use crate::Error;
+
+/// Rows or glyphs from `start` up to and including `end`, or to the last one if `end` is `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl Span {
+    pub const ALL: Span = Span {
+        start: 0,
+        end: None,
+    };
+
+    /// Like `3`, `3-5`, or `3-` for 3 to the end.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (start, end) = match s.split_once('-') {
+            Some((start, "")) => (start.parse().ok()?, None),
+            Some((start, end)) => (start.parse().ok()?, Some(end.parse().ok()?)),
+            None => (s.parse().ok()?, Some(s.parse().ok()?)),
+        };
+        Some(Span { start, end }).filter(|span| span.end.is_none_or(|end| end >= span.start))
+    }
+
+    /// The indexes of `len` things that are in this span, or `None` if the span starts past the
+    /// end or ends past it.
+    fn select(self, len: usize) -> Option<Range<usize>> {
+        let end = self.end.map_or(len, |end| end + 1);
+        (self.start <= len && end <= len).then_some(self.start..end)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.end {
+            Some(end) if end == self.start => write!(f, "{end}"),
+            Some(end) => write!(f, "{}-{end}", self.start),
+            None => write!(f, "{}-", self.start),
+        }
+    }
+}
+
+/// Some rows of a source, and maybe only some glyphs in those rows, like `ModB@6-7:0-20`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector<'s> {
+    pub source: &'s str,
+    pub rows: Span,
+    pub glyphs: Span,
+    /// Add the glyphs to the end of the last row instead of starting new rows. Written with a
+    /// leading `+`.
+    pub append: bool,
+}
+
+impl<'s> Selector<'s> {
+    /// `[+]SOURCE[@ROWS[:GLYPHS]]`. Without `@ROWS`, every row.
+    pub fn parse(s: &'s str) -> Option<Self> {
+        let (append, s) = match s.strip_prefix('+') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let spans = s.rsplit_once('@').and_then(|(source, spans)| {
+            let (rows, glyphs) = match spans.split_once(':') {
+                Some((rows, glyphs)) => (Span::parse(rows)?, Span::parse(glyphs)?),
+                None => (Span::parse(spans)?, Span::ALL),
+            };
+            Some((source, rows, glyphs))
+        });
+        let (source, rows, glyphs) = spans.unwrap_or((s, Span::ALL, Span::ALL));
+        Some(Selector {
+            source,
+            rows,
+            glyphs,
+            append,
+        })
+        .filter(|selector| !selector.source.is_empty())
+    }
+}
+
+impl fmt::Display for Selector<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let append = if self.append { "+" } else { "" };
+        write!(f, "{append}{}@{}", self.source, self.rows)?;
+        if self.glyphs != Span::ALL {
+            write!(f, ":{}", self.glyphs)?;
+        }
+        Ok(())
+    }
+}
+
+/// Build rows out of glyphs from each selector's source, in order. `source` finds the glyphs
+/// for a selector's source.
+pub fn compose<'l>(
+    selectors: &[Selector],
+    source: impl Fn(&str) -> &'l LoadedIndex,
+) -> Result<LoadedIndex, Error> {
+    let mut rows = Vec::<Vec<LoadedGlyph>>::new();
+    for selector in selectors {
+        let loaded = source(selector.source);
+        let picked = selector
+            .rows
+            .select(loaded.rows.len())
+            .ok_or_else(|| Error::SelectorRows {
+                selector: selector.to_string(),
+                name: selector.source.to_owned(),
+                rows: loaded.rows.len(),
+            })?
+            .map(|r| {
+                let row = &loaded.rows[r];
+                let glyphs =
+                    selector
+                        .glyphs
+                        .select(row.len())
+                        .ok_or_else(|| Error::SelectorGlyphs {
+                            selector: selector.to_string(),
+                            row: r,
+                            len: row.len(),
+                        })?;
+                /* positions in one source mean nothing next to glyphs from another */
+                let mut row = row[glyphs].to_vec();
+                for loaded in &mut row {
+                    loaded.glyph.at = None;
+                }
+                Ok(row)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut picked = picked.into_iter();
+        if selector.append {
+            let first = picked.next().unwrap_or_default();
+            match rows.last_mut() {
+                Some(last) => last.extend(first),
+                None => rows.push(first),
+            }
+        }
+        rows.extend(picked);
+    }
+    Ok(LoadedIndex { rows })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::IndexGlyph;
+    use image::RgbaImage;
+
+    #[test]
+    fn test_compose() {
+        let selector = |s| Selector::parse(s).unwrap();
+        assert_eq!(
+            selector("+C:\\mods\\B@GameFont@6:10-"),
+            Selector {
+                source: "C:\\mods\\B@GameFont",
+                rows: Span {
+                    start: 6,
+                    end: Some(6)
+                },
+                glyphs: Span {
+                    start: 10,
+                    end: None
+                },
+                append: true,
+            }
+        );
+        assert_eq!(selector("someone@example").rows, Span::ALL);
+        assert_eq!(Span::parse("5-3"), None);
+
+        let numbered = |lengths: &[usize]| LoadedIndex {
+            rows: lengths
+                .iter()
+                .enumerate()
+                .map(|(r, &len)| {
+                    (0..len)
+                        .map(|c| LoadedGlyph {
+                            glyph: IndexGlyph {
+                                path: format!("{r}-{c}.png"),
+                                ..IndexGlyph::default()
+                            },
+                            image: RgbaImage::new(1, 1),
+                        })
+                        .collect()
+                })
+                .collect(),
+        };
+        let a = numbered(&[3, 3, 3]);
+        let b = numbered(&[4, 4]);
+        let composed = compose(
+            &[selector("a@0-1"), selector("b@1"), selector("+a@2:1-")],
+            |source| if source == "a" { &a } else { &b },
+        )
+        .unwrap();
+        let paths = composed
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|l| l.glyph.path.as_str())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                vec!["0-0.png", "0-1.png", "0-2.png"],
+                vec!["1-0.png", "1-1.png", "1-2.png"],
+                vec!["1-0.png", "1-1.png", "1-2.png", "1-3.png", "2-1.png", "2-2.png"],
+            ]
+        );
+        assert!(compose(&[selector("b@2")], |_| &b).is_err());
+    }
+}
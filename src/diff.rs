@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::io::Cursor;
+
+use base64::Engine;
+use image::{ImageFormat, RgbaImage};
+
+use crate::index::{html_encoded, LoadedGlyph, LoadedIndex};
+use crate::Error;
+
+/// One side of a diff.
+pub struct Side {
+    pub loaded: LoadedIndex,
+    /// False for numbered paths from unpacking a .tga in memory, since those say where a glyph is
+    /// and not what it is.
+    pub paths_are_names: bool,
+}
+
+/// A row and position in the row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Slot {
+    pub row: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.row, self.col)
+    }
+}
+
+/// How a glyph differs between the old and new side. A glyph only on one side was inserted or
+/// removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlyphDiff {
+    pub label: String,
+    pub old: Option<Slot>,
+    pub new: Option<Slot>,
+    /// in a different row, or in a different order relative to the other glyphs in its row, and
+    /// not just shifted by glyphs inserted or removed before it
+    pub moved: bool,
+    pub resized: Option<((u32, u32), (u32, u32))>,
+    /// pixels that differ, only counted if the glyph is the same size on both sides
+    pub pixels: usize,
+    pub descent: Option<(u32, u32)>,
+}
+
+impl GlyphDiff {
+    pub fn is_unchanged(&self) -> bool {
+        self.old.is_some()
+            && self.new.is_some()
+            && !self.moved
+            && self.resized.is_none()
+            && self.pixels == 0
+            && self.descent.is_none()
+    }
+
+    /// Like `inserted` or `moved, resized 20x20 -> 22x20, descent 3 -> 4`.
+    pub fn details(&self) -> String {
+        match (self.old, self.new) {
+            (None, _) => return "inserted".to_owned(),
+            (_, None) => return "removed".to_owned(),
+            _ => (),
+        }
+        let mut details = Vec::new();
+        if self.moved {
+            details.push("moved".to_owned());
+        }
+        if let Some(((ow, oh), (nw, nh))) = self.resized {
+            details.push(format!("resized {ow}x{oh} -> {nw}x{nh}"));
+        }
+        if self.pixels > 0 {
+            details.push(format!("{} pixels changed", self.pixels));
+        }
+        if let Some((old, new)) = self.descent {
+            details.push(format!("descent {old} -> {new}"));
+        }
+        details.join(", ")
+    }
+}
+
+impl fmt::Display for GlyphDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match (self.old, self.new) {
+            (None, _) => '+',
+            (_, None) => '-',
+            _ => '~',
+        };
+        let slots = match (self.old, self.new) {
+            (Some(old), Some(new)) if old != new => format!("{old} -> {new}"),
+            (Some(slot), _) | (_, Some(slot)) => slot.to_string(),
+            (None, None) => String::new(),
+        };
+        write!(
+            f,
+            "{sign} {:<20} {slots:<14} {}",
+            self.label,
+            self.details()
+        )
+    }
+}
+
+/// Pair up the glyphs of `old` and `new` and describe how each pair differs, in the order of the
+/// new side with removed glyphs last. Unchanged glyphs are included.
+///
+/// Glyphs are paired by name or character, then path if `paths_are_names`. Glyphs left over are
+/// paired if they have identical pixels, so a glyph that moved is found even without a name, and
+/// what's left after that is paired by position.
+pub fn diff(old: &Side, new: &Side) -> Vec<GlyphDiff> {
+    let old_glyphs = slots(&old.loaded);
+    let new_glyphs = slots(&new.loaded);
+    let mut pairs = vec![None::<usize>; old_glyphs.len()];
+    let mut taken = vec![false; new_glyphs.len()];
+
+    let mut by_key = HashMap::new();
+    for (j, (_, loaded)) in new_glyphs.iter().enumerate().rev() {
+        if let Some(key) = key(loaded, new.paths_are_names) {
+            by_key.insert(key, j);
+        }
+    }
+    for (i, (_, loaded)) in old_glyphs.iter().enumerate() {
+        let found = key(loaded, old.paths_are_names).and_then(|key| by_key.get(&key));
+        if let Some(&j) = found.filter(|&&j| !taken[j]) {
+            pairs[i] = Some(j);
+            taken[j] = true;
+        }
+    }
+
+    let mut by_pixels = HashMap::<_, Vec<usize>>::new();
+    for (j, (_, loaded)) in new_glyphs.iter().enumerate().rev() {
+        if !taken[j] {
+            by_pixels
+                .entry(pixels_of(&loaded.image))
+                .or_default()
+                .push(j);
+        }
+    }
+    for (i, (_, loaded)) in old_glyphs.iter().enumerate() {
+        if pairs[i].is_none() {
+            if let Some(j) = by_pixels
+                .get_mut(&pixels_of(&loaded.image))
+                .and_then(|js| js.pop())
+            {
+                pairs[i] = Some(j);
+                taken[j] = true;
+            }
+        }
+    }
+
+    let by_slot = new_glyphs
+        .iter()
+        .enumerate()
+        .map(|(j, (slot, _))| (*slot, j))
+        .collect::<HashMap<_, _>>();
+    for (i, (slot, _)) in old_glyphs.iter().enumerate() {
+        if pairs[i].is_none() {
+            if let Some(&j) = by_slot.get(slot).filter(|&&j| !taken[j]) {
+                pairs[i] = Some(j);
+                taken[j] = true;
+            }
+        }
+    }
+
+    let mut old_of = vec![None; new_glyphs.len()];
+    pairs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, j)| Some((i, (*j)?)))
+        .for_each(|(i, j)| old_of[j] = Some(i));
+
+    /* in each row, the most glyphs that stayed in the same order aren't moved */
+    let mut stayed = vec![false; new_glyphs.len()];
+    let mut row = Vec::<(usize, usize)>::new();
+    for (j, (slot, _)) in new_glyphs.iter().enumerate() {
+        if let Some(i) = old_of[j].filter(|&i| old_glyphs[i].0.row == slot.row) {
+            row.push((j, i));
+        }
+        let row_ends = new_glyphs
+            .get(j + 1)
+            .is_none_or(|next| next.0.row != slot.row);
+        if row_ends {
+            let order = row.iter().map(|&(_, i)| i).collect::<Vec<_>>();
+            for (&(j, _), in_order) in row.iter().zip(longest_increasing(&order)) {
+                stayed[j] = in_order;
+            }
+            row.clear();
+        }
+    }
+
+    let paired = new_glyphs.iter().enumerate().map(|(j, new)| {
+        let mut diff = compare(old_of[j].map(|i| &old_glyphs[i]), Some(new));
+        diff.moved = old_of[j].is_some() && !stayed[j];
+        diff
+    });
+    let removed = old_glyphs
+        .iter()
+        .zip(&pairs)
+        .filter(|(_, j)| j.is_none())
+        .map(|(old, _)| compare(Some(old), None));
+    paired.chain(removed).collect()
+}
+
+/// Which elements of `seq` are in its longest strictly increasing subsequence.
+pub(crate) fn longest_increasing(seq: &[usize]) -> Vec<bool> {
+    /* lengths[i] is the longest subsequence ending at i, rows are short enough for O(n²) */
+    let mut lengths = vec![1; seq.len()];
+    let mut prev = vec![None; seq.len()];
+    for i in 0..seq.len() {
+        for k in 0..i {
+            if seq[k] < seq[i] && lengths[k] + 1 > lengths[i] {
+                lengths[i] = lengths[k] + 1;
+                prev[i] = Some(k);
+            }
+        }
+    }
+    let mut in_order = vec![false; seq.len()];
+    let mut at = (0..seq.len()).max_by_key(|&i| (lengths[i], std::cmp::Reverse(i)));
+    while let Some(i) = at {
+        in_order[i] = true;
+        at = prev[i];
+    }
+    in_order
+}
+
+/// Every glyph with its row and position, in order.
+pub fn slots(loaded: &LoadedIndex) -> Vec<(Slot, &LoadedGlyph)> {
+    loaded
+        .rows
+        .iter()
+        .enumerate()
+        .flat_map(|(row, glyphs)| {
+            glyphs
+                .iter()
+                .enumerate()
+                .map(move |(col, loaded)| (Slot { row, col }, loaded))
+        })
+        .collect()
+}
+
+fn key(loaded: &LoadedGlyph, paths_are_names: bool) -> Option<String> {
+    let glyph = &loaded.glyph;
+    (paths_are_names || glyph.name.is_some() || glyph.char.is_some()).then(|| glyph.key())
+}
+
+fn pixels_of(image: &RgbaImage) -> (u32, u32, &[u8]) {
+    (image.width(), image.height(), image.as_raw())
+}
+
+fn compare(old: Option<&(Slot, &LoadedGlyph)>, new: Option<&(Slot, &LoadedGlyph)>) -> GlyphDiff {
+    /* a numbered .tga on one side shouldn't hide the name from the other */
+    let title =
+        |side: Option<&(Slot, &LoadedGlyph)>| side.and_then(|(_, loaded)| loaded.glyph.title());
+    let label = title(new)
+        .or_else(|| title(old))
+        .or_else(|| new.or(old).map(|(_, loaded)| loaded.glyph.path.clone()))
+        .unwrap_or_default();
+    let mut diff = GlyphDiff {
+        label,
+        old: old.map(|(slot, _)| *slot),
+        new: new.map(|(slot, _)| *slot),
+        moved: false,
+        resized: None,
+        pixels: 0,
+        descent: None,
+    };
+    let (Some((_, old)), Some((_, new))) = (old, new) else {
+        return diff;
+    };
+
+    if old.image.dimensions() != new.image.dimensions() {
+        diff.resized = Some((old.image.dimensions(), new.image.dimensions()));
+    } else {
+        diff.pixels = old
+            .image
+            .pixels()
+            .zip(new.image.pixels())
+            .filter(|(a, b)| a != b)
+            .count();
+    }
+    if old.glyph.descent != new.glyph.descent {
+        diff.descent = Some((old.glyph.descent, new.glyph.descent));
+    }
+    diff
+}
+
+/// A page listing each changed glyph with its old and new images side by side.
+pub fn html_report(old: &Side, new: &Side, diffs: &[GlyphDiff]) -> Result<String, Error> {
+    let old_glyphs = slots(&old.loaded)
+        .into_iter()
+        .collect::<HashMap<Slot, &LoadedGlyph>>();
+    let new_glyphs = slots(&new.loaded)
+        .into_iter()
+        .collect::<HashMap<Slot, &LoadedGlyph>>();
+
+    let mut s = String::from(
+        r#"<!DOCTYPE html>
+<head>
+<meta charset="utf-8">
+<style>
+body
+  { background: #282828; color: #ddd; font-family: sans-serif }
+td
+  { padding: 2px 8px }
+img
+  { zoom: 2; image-rendering: pixelated; background: #444 }
+</style>
+</head>
+<table>
+<tr><th>glyph</th><th>changes</th><th>old</th><th>new</th><th></th><th></th></tr>
+"#,
+    );
+    for diff in diffs.iter().filter(|diff| !diff.is_unchanged()) {
+        let slot = |slot: Option<Slot>| slot.map(|slot| slot.to_string()).unwrap_or_default();
+        let img = |glyph: Option<&&LoadedGlyph>| -> Result<String, Error> {
+            match glyph {
+                Some(loaded) => Ok(format!("<img src='{}'>", data_uri(&loaded.image)?)),
+                None => Ok(String::new()),
+            }
+        };
+        let old_img = img(diff.old.and_then(|slot| old_glyphs.get(&slot)))?;
+        let new_img = img(diff.new.and_then(|slot| new_glyphs.get(&slot)))?;
+        let _ = writeln!(
+            s,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{old_img}</td><td>{new_img}</td></tr>",
+            html_encoded(&diff.label),
+            html_encoded(&diff.details()),
+            slot(diff.old),
+            slot(diff.new),
+        );
+    }
+    s.push_str("</table>\n");
+    Ok(s)
+}
+
+fn data_uri(image: &RgbaImage) -> Result<String, Error> {
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(format!(
+        "data:image/png;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(png)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::IndexGlyph;
+    use image::Rgba;
+
+    fn numbered(rows: &[&[u8]]) -> Side {
+        let mut i = 0;
+        let rows = rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&shade| {
+                        i += 1;
+                        LoadedGlyph {
+                            glyph: IndexGlyph {
+                                path: format!("{:03}.png", i - 1),
+                                ..IndexGlyph::default()
+                            },
+                            image: RgbaImage::from_pixel(2, 2, Rgba([shade, shade, shade, 255])),
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Side {
+            loaded: LoadedIndex { rows },
+            paths_are_names: false,
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = numbered(&[&[1, 2, 3, 7], &[4, 5]]);
+        let mut new = numbered(&[&[1, 9, 3, 2, 7], &[4, 6]]);
+        new.loaded.rows[1][0].glyph.descent = 2;
+
+        let diffs = diff(&old, &new)
+            .into_iter()
+            .filter(|diff| !diff.is_unchanged())
+            .map(|diff| (diff.old, diff.new, diff.details()))
+            .collect::<Vec<_>>();
+        let slot = |row, col| Some(Slot { row, col });
+        assert_eq!(
+            diffs,
+            vec![
+                (None, slot(0, 1), "inserted".to_owned()),
+                (slot(0, 1), slot(0, 3), "moved".to_owned()),
+                (slot(1, 0), slot(1, 0), "descent 0 -> 2".to_owned()),
+                (slot(1, 1), slot(1, 1), "4 pixels changed".to_owned()),
+            ]
+        );
+    }
+}
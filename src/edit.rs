@@ -0,0 +1,250 @@
+use image::{GenericImageView, RgbaImage};
+
+use crate::atlas::{Atlas, Glyph};
+use crate::index::{copy_glyph_to_atlas, Index, IndexGlyph, FRAME, FRAME_WIDTH};
+use crate::point::Point;
+use crate::Error;
+
+/// How `replace_glyph` fit the new image in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replaced {
+    /// same size, only the glyph's own pixels changed
+    InPlace,
+    /// the glyphs after it in its row were moved over
+    Reflowed,
+    /// it didn't fit in its row, so the whole atlas was packed again
+    Repacked,
+}
+
+/// The glyph at `row`, `col`, or an error saying how many there are.
+pub fn find_glyph(atlas: &Atlas, (row, col): (usize, usize)) -> Result<Glyph, Error> {
+    let glyphs = atlas.rows.get(row).ok_or(Error::NoRow {
+        row,
+        rows: atlas.rows.len(),
+    })?;
+    glyphs.get(col).copied().ok_or(Error::NoGlyph {
+        row,
+        col,
+        len: glyphs.len(),
+    })
+}
+
+/// Draw `image` over the glyph at `row`, `col` with the glyph's baseline marker, or `descent` if
+/// given. Everything outside of the glyph's row is left alone unless it has to be packed again.
+pub fn replace_glyph(
+    buf: &mut RgbaImage,
+    at: (usize, usize),
+    image: &RgbaImage,
+    descent: Option<u32>,
+) -> Result<Replaced, Error> {
+    let atlas = Atlas::from_image(buf);
+    let old = find_glyph(&atlas, at)?;
+    let descent = descent.unwrap_or(old.descent).min(image.height());
+    let (row, col) = at;
+
+    if (old.w(), old.h()) == image.dimensions() {
+        drop(atlas);
+        /* the old baseline marker is in the frame to the right */
+        for y in old.tl.y..=old.br.y {
+            buf.put_pixel(old.br.x + FRAME_WIDTH, y, FRAME);
+        }
+        copy_glyph_to_atlas(buf, old.tl, image, descent);
+        return Ok(Replaced::InPlace);
+    }
+
+    /* the row's glyphs each keep their y, everything from the new one on moves over in x */
+    let top = atlas.rows[row].iter().map(|g| g.tl.y).min().unwrap_or(0);
+    let bottom = match atlas.rows.get(row + 1) {
+        Some(next) => next.iter().map(|g| g.tl.y).min().unwrap_or(buf.height()),
+        None => buf.height(),
+    };
+    let mut moved = vec![(old.tl.y, image.clone(), descent)];
+    for glyph in &atlas.rows[row][col + 1..] {
+        let image = buf
+            .view(glyph.x(), glyph.y(), glyph.w(), glyph.h())
+            .to_image();
+        moved.push((glyph.tl.y, image, glyph.descent));
+    }
+    let right = moved
+        .iter()
+        .map(|(_, image, _)| image.width() + FRAME_WIDTH)
+        .sum::<u32>()
+        + old.tl.x;
+    let fits = right <= buf.width()
+        && moved
+            .iter()
+            .all(|(y, image, _)| y + image.height() + FRAME_WIDTH <= bottom);
+
+    if !fits {
+        let mut loaded = atlas.load_images(atlas.to_index());
+        let glyph = &mut loaded.rows[row][col];
+        glyph.image = image.clone();
+        glyph.glyph.descent = descent;
+        let (width, height) = loaded.packed_size();
+        if width > buf.width() || height > buf.height() {
+            return Err(Error::AtlasTooSmall {
+                needed: (width, height),
+                glyph: image.dimensions(),
+                atlas: buf.dimensions(),
+            });
+        }
+        *buf = loaded.to_atlas_image(buf.dimensions())?;
+        return Ok(Replaced::Repacked);
+    }
+
+    drop(atlas);
+    for y in top..bottom {
+        for x in old.tl.x..buf.width() {
+            buf.put_pixel(x, y, FRAME);
+        }
+    }
+    let mut x = old.tl.x;
+    for (y, image, descent) in moved {
+        copy_glyph_to_atlas(buf, Point { x, y }, &image, descent);
+        x += image.width() + FRAME_WIDTH;
+    }
+    Ok(Replaced::Reflowed)
+}
+
+/// The glyph at `row`, `col` in an index, or an error saying how many there are.
+pub fn index_glyph_mut(
+    index: &mut Index,
+    (row, col): (usize, usize),
+) -> Result<&mut IndexGlyph, Error> {
+    let rows = index.rows.len();
+    let glyphs = index.rows.get_mut(row).ok_or(Error::NoRow { row, rows })?;
+    let len = glyphs.len();
+    glyphs.get_mut(col).ok_or(Error::NoGlyph { row, col, len })
+}
+
+/// Move the glyph at `from` to just before the glyph at `to`, both counted before the move. `to`
+/// can be just past the end of a row, or a row past the last for a new row. Rows left empty are
+/// removed, and the glyph forgets where it was unpacked from so `--keep-layout` puts it after the
+/// glyph before it.
+pub fn move_glyph(
+    index: &mut Index,
+    from: (usize, usize),
+    to: (usize, usize),
+) -> Result<(), Error> {
+    index_glyph_mut(index, from)?;
+    let (row, mut col) = to;
+    match index.rows.get(row) {
+        Some(glyphs) if col <= glyphs.len() => (),
+        Some(glyphs) => {
+            let len = glyphs.len();
+            return Err(Error::NoGlyph { row, col, len });
+        }
+        None if row == index.rows.len() && col == 0 => index.rows.push(vec![]),
+        None => {
+            let rows = index.rows.len();
+            return Err(Error::NoRow { row, rows });
+        }
+    }
+
+    let mut glyph = index.rows[from.0].remove(from.1);
+    glyph.at = None;
+    if row == from.0 && col > from.1 {
+        col -= 1;
+    }
+    index.rows[row].insert(col, glyph);
+    index.rows.retain(|glyphs| !glyphs.is_empty());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{LoadedGlyph, LoadedIndex};
+    use image::Rgba;
+
+    fn solid(w: u32, h: u32, shade: u8) -> RgbaImage {
+        RgbaImage::from_pixel(w, h, Rgba([shade, shade, shade, 255]))
+    }
+
+    fn sizes(buf: &RgbaImage) -> Vec<Vec<(u32, u32, u32)>> {
+        Atlas::from_image(buf)
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|g| (g.w(), g.h(), g.descent)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_replace_glyph() {
+        let loaded = LoadedIndex {
+            rows: [[(2, 3), (3, 3), (2, 2)], [(4, 4), (1, 4), (2, 4)]]
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|&(w, h)| LoadedGlyph {
+                            glyph: Default::default(),
+                            image: solid(w, h, 200),
+                        })
+                        .collect()
+                })
+                .collect(),
+        };
+        let mut buf = loaded.to_atlas_image((12, 12)).unwrap();
+
+        let replaced = replace_glyph(&mut buf, (0, 1), &solid(3, 3, 100), Some(1)).unwrap();
+        assert_eq!(replaced, Replaced::InPlace);
+        assert_eq!(buf.get_pixel(3, 0), &Rgba([100, 100, 100, 255]));
+        assert_eq!(sizes(&buf)[0][1], (3, 3, 1));
+
+        let replaced = replace_glyph(&mut buf, (0, 0), &solid(4, 2, 100), None).unwrap();
+        assert_eq!(replaced, Replaced::Reflowed);
+        assert_eq!(sizes(&buf)[0], [(4, 2, 0), (3, 3, 1), (2, 2, 0)]);
+        assert_eq!(sizes(&buf)[1], [(4, 4, 0), (1, 4, 0), (2, 4, 0)]);
+
+        let replaced = replace_glyph(&mut buf, (0, 2), &solid(2, 5, 100), None).unwrap();
+        assert_eq!(replaced, Replaced::Repacked);
+        assert_eq!(sizes(&buf)[0], [(4, 2, 0), (3, 3, 1), (2, 5, 0)]);
+
+        assert!(replace_glyph(&mut buf, (1, 0), &solid(12, 1, 100), None).is_err());
+        assert!(replace_glyph(&mut buf, (2, 0), &solid(1, 1, 100), None).is_err());
+    }
+
+    #[test]
+    fn test_move_glyph() {
+        let mut index = Index {
+            rows: vec![vec!["a", "b", "c"], vec!["d"]]
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|path| IndexGlyph {
+                            path: path.to_owned(),
+                            at: Some((0, 0)),
+                            ..IndexGlyph::default()
+                        })
+                        .collect()
+                })
+                .collect(),
+            size: None,
+        };
+        let paths = |index: &Index| {
+            index
+                .rows
+                .iter()
+                .map(|row| row.iter().map(|g| g.path.as_str()).collect::<String>())
+                .collect::<Vec<_>>()
+        };
+
+        move_glyph(&mut index, (0, 0), (0, 2)).unwrap();
+        assert_eq!(paths(&index), ["bac", "d"]);
+        assert_eq!(index.rows[0][1].at, None);
+        move_glyph(&mut index, (0, 2), (0, 0)).unwrap();
+        assert_eq!(paths(&index), ["cba", "d"]);
+        move_glyph(&mut index, (0, 1), (1, 1)).unwrap();
+        assert_eq!(paths(&index), ["ca", "db"]);
+        move_glyph(&mut index, (0, 0), (2, 0)).unwrap();
+        assert_eq!(paths(&index), ["a", "db", "c"]);
+        /* emptying a row removes it */
+        move_glyph(&mut index, (0, 0), (1, 2)).unwrap();
+        assert_eq!(paths(&index), ["dba", "c"]);
+
+        assert!(move_glyph(&mut index, (0, 3), (0, 0)).is_err());
+        assert!(move_glyph(&mut index, (0, 0), (0, 4)).is_err());
+        assert!(move_glyph(&mut index, (0, 0), (3, 0)).is_err());
+        assert_eq!(paths(&index), ["dba", "c"]);
+    }
+}
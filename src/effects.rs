@@ -0,0 +1,227 @@
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+
+use crate::index::{LoadedGlyph, LoadedIndex};
+
+/// Outline and drop shadow, like the options of the same name in atlast.html except these are
+/// done on the glyph image after it's rasterized instead of by the canvas while drawing text.
+#[derive(Debug, Clone)]
+pub struct Effects {
+    pub shadow_color: Rgba<u8>,
+    pub shadow_x: i32,
+    pub shadow_y: i32,
+    /// like canvas `shadowBlur`, the gaussian's standard deviation is half of this
+    pub shadow_blur: f32,
+    pub outline_color: Rgba<u8>,
+    /// like canvas `lineWidth` for `strokeText()`, half of this is outside the glyph
+    pub outline_thickness: f32,
+    /// glyphs are padded on each side by this times `outline_thickness`
+    pub outline_factor: f32,
+}
+
+impl Default for Effects {
+    fn default() -> Self {
+        Effects {
+            shadow_color: Rgba([33, 33, 33, 204]),
+            shadow_x: 0,
+            shadow_y: 0,
+            shadow_blur: 0.,
+            outline_color: Rgba([33, 33, 33, 204]),
+            outline_thickness: 0.,
+            outline_factor: 0.5,
+        }
+    }
+}
+
+/// How many pixels an image grows on each side.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Padding {
+    pub left: u32,
+    pub right: u32,
+    pub up: u32,
+    pub down: u32,
+}
+
+impl Effects {
+    pub fn is_noop(&self) -> bool {
+        !self.has_outline() && !self.has_shadow()
+    }
+
+    fn has_outline(&self) -> bool {
+        self.outline_thickness > 0. && self.outline_color[3] > 0
+    }
+
+    /// Like canvas, shadows are only drawn when they're offset or blurred.
+    fn has_shadow(&self) -> bool {
+        self.shadow_color[3] > 0
+            && (self.shadow_x != 0 || self.shadow_y != 0 || self.shadow_blur > 0.)
+    }
+
+    /// Same as atlast.html's `shadowLeft`, `shadowRight`, etc. but also makes room for blur.
+    pub fn padding(&self) -> Padding {
+        let outline = match self.has_outline() {
+            true => (self.outline_factor * self.outline_thickness).ceil() as u32,
+            false => 0,
+        };
+        let (x, y, blur) = match self.has_shadow() {
+            true => (
+                self.shadow_x,
+                self.shadow_y,
+                self.shadow_blur.max(0.).ceil() as u32,
+            ),
+            false => (0, 0, 0),
+        };
+        Padding {
+            left: outline + blur + x.min(0).unsigned_abs(),
+            right: outline + blur + x.max(0).unsigned_abs(),
+            up: outline + blur + y.min(0).unsigned_abs(),
+            down: outline + blur + y.max(0).unsigned_abs(),
+        }
+    }
+
+    /// Returns a new image, padded to fit the outline and shadow, and the descent adjusted for
+    /// the padding added below the glyph.
+    pub fn apply(&self, image: &RgbaImage, descent: u32) -> (RgbaImage, u32) {
+        if self.is_noop() {
+            return (image.clone(), descent);
+        }
+
+        let pad = self.padding();
+        let mut glyph = RgbaImage::new(
+            image.width() + pad.left + pad.right,
+            image.height() + pad.up + pad.down,
+        );
+        image::imageops::replace(&mut glyph, image, pad.left as i64, pad.up as i64);
+
+        if self.has_outline() {
+            let outline = colorize(
+                &dilate(&alpha_of(&glyph), self.outline_thickness / 2.),
+                self.outline_color,
+            );
+            glyph = source_over(&glyph, &outline);
+        }
+
+        if self.has_shadow() {
+            let mut mask = GrayImage::new(glyph.width(), glyph.height());
+            image::imageops::replace(
+                &mut mask,
+                &alpha_of(&glyph),
+                self.shadow_x as i64,
+                self.shadow_y as i64,
+            );
+            if self.shadow_blur > 0. {
+                mask = image::imageops::blur(&mask, self.shadow_blur / 2.);
+            }
+            glyph = source_over(&glyph, &colorize(&mask, self.shadow_color));
+        }
+
+        (glyph, grow_descent(descent, pad.down))
+    }
+
+    pub fn apply_to_glyph(&self, loaded: &mut LoadedGlyph) {
+        let (image, descent) = self.apply(&loaded.image, loaded.glyph.descent);
+        loaded.image = image;
+        loaded.glyph.descent = descent;
+    }
+
+    pub fn apply_to_index(&self, loaded: &mut LoadedIndex) {
+        loaded
+            .rows
+            .iter_mut()
+            .flat_map(|row| row.iter_mut())
+            .for_each(|loaded| self.apply_to_glyph(loaded));
+    }
+}
+
+/// A descent of zero means no baseline marker, so the bottom row of the glyph sits on the
+/// baseline. That's the same as a descent of one, so if the glyph grows down it needs a marker.
+pub fn grow_descent(descent: u32, down: u32) -> u32 {
+    match (descent, down) {
+        (0, 0) => 0,
+        (0, down) => down + 1,
+        (descent, down) => descent + down,
+    }
+}
+
+fn alpha_of(image: &RgbaImage) -> GrayImage {
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        Luma([image.get_pixel(x, y)[3]])
+    })
+}
+
+/// Grow visible areas of `mask` by `radius` pixels with round corners, like a stroke with
+/// `lineJoin = 'round'`. Edges are anti-aliased by how far the pixel is past the radius.
+fn dilate(mask: &GrayImage, radius: f32) -> GrayImage {
+    let reach = radius.ceil() as i64 + 1;
+    GrayImage::from_fn(mask.width(), mask.height(), |x, y| {
+        let mut best = 0f32;
+        for dy in -reach..=reach {
+            for dx in -reach..=reach {
+                let (Ok(nx), Ok(ny)) = (u32::try_from(x as i64 + dx), u32::try_from(y as i64 + dy))
+                else {
+                    continue;
+                };
+                let Some(&Luma([a])) = mask.get_pixel_checked(nx, ny) else {
+                    continue;
+                };
+                let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                let coverage = (radius + 0.5 - distance).clamp(0., 1.);
+                best = best.max(a as f32 * coverage);
+            }
+        }
+        Luma([best.round() as u8])
+    })
+}
+
+fn colorize(mask: &GrayImage, Rgba([r, g, b, a]): Rgba<u8>) -> RgbaImage {
+    RgbaImage::from_fn(mask.width(), mask.height(), |x, y| {
+        let Luma([m]) = *mask.get_pixel(x, y);
+        match (m as u32 * a as u32 + 127) / 255 {
+            0 => Rgba([0, 0, 0, 0]),
+            alpha => Rgba([r, g, b, alpha as u8]),
+        }
+    })
+}
+
+/// Composite `top` over `bottom`, both images must be the same size. Fully transparent pixels are
+/// always black so they can't be mistaken for frame pixels.
+fn source_over(top: &RgbaImage, bottom: &RgbaImage) -> RgbaImage {
+    RgbaImage::from_fn(top.width(), top.height(), |x, y| {
+        let Rgba(t) = *top.get_pixel(x, y);
+        let Rgba(b) = *bottom.get_pixel(x, y);
+        let ta = t[3] as f32 / 255.;
+        let ba = b[3] as f32 / 255.;
+        let a = ta + ba * (1. - ta);
+        if a <= 0. {
+            return Rgba([0, 0, 0, 0]);
+        }
+        let channel =
+            |i: usize| ((t[i] as f32 * ta + b[i] as f32 * ba * (1. - ta)) / a).round() as u8;
+        Rgba([channel(0), channel(1), channel(2), (a * 255.).round() as u8])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shadow_grows_glyph_and_descent() {
+        let mut image = RgbaImage::new(3, 5);
+        image.put_pixel(1, 1, Rgba([255, 255, 255, 255]));
+
+        let effects = Effects {
+            shadow_x: 1,
+            shadow_y: 2,
+            ..Effects::default()
+        };
+        let (shadowed, descent) = effects.apply(&image, 2);
+
+        assert_eq!(shadowed.dimensions(), (4, 7));
+        assert_eq!(descent, 4);
+        assert_eq!(*shadowed.get_pixel(1, 1), Rgba([255, 255, 255, 255]));
+        assert_eq!(*shadowed.get_pixel(2, 3), effects.shadow_color);
+        assert_eq!(*shadowed.get_pixel(0, 0), Rgba([0, 0, 0, 0]));
+
+        assert_eq!(effects.apply(&image, 0).1, 3);
+    }
+}
@@ -1,10 +1,12 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{self, Write};
 use std::path::Path;
 
-use anyhow::{Context, Result};
 use image::{Rgba, RgbaImage};
 
+use crate::manifest::ManifestError;
 use crate::point::Point;
+use crate::Error;
 
 pub const FRAME: Rgba<u8> = Rgba([255, 0, 255, 0]) /* hot pink */;
 pub const BASELINE: Rgba<u8> = Rgba([0, 255, 255, 0]) /* teal */;
@@ -14,21 +16,90 @@ pub fn is_frameish(rgba: Rgba<u8>) -> bool {
     rgba == FRAME || rgba == BASELINE
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Index {
     pub rows: Vec<Vec<IndexGlyph>>,
+    /// The width and height of the atlas this was unpacked from.
+    pub size: Option<(u32, u32)>,
+}
+
+/// Where each glyph of a `LoadedIndex` goes in an atlas, in the same rows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub size: (u32, u32),
+    pub positions: Vec<Vec<Point>>,
+}
+
+/// A glyph that runs off the edge of the atlas it's packed into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clipped {
+    pub path: String,
+    pub row: usize,
+    pub at: Point,
+    /// without the frame
+    pub size: (u32, u32),
+}
+
+impl fmt::Display for Clipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Clipped {
+            path,
+            row,
+            at,
+            size,
+        } = self;
+        write!(
+            f,
+            "{path} in row {row}, {}x{} at {},{}",
+            size.0, size.1, at.x, at.y
+        )
+    }
 }
 
 impl Index {
     pub fn len(&self) -> usize {
         self.rows.iter().map(|row| row.len()).sum()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|row| row.is_empty())
+    }
 }
 
-#[derive(Debug, Clone)]
+impl LoadedIndex {
+    pub fn len(&self) -> usize {
+        self.rows.iter().map(|row| row.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|row| row.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct IndexGlyph {
     pub path: String,
+    #[serde(default, skip_serializing_if = "is_zero")]
     pub descent: u32,
+    /// The character this glyph draws, if it's in the text portion of the atlas.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub char: Option<char>,
+    /// A human readable label, like `gold` or `religion_judaism`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Where the glyph's top left pixel was in the atlas it was unpacked from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at: Option<(u32, u32)>,
+    /// Anything else about the glyph, not used for packing. These are `data-*` attributes in the
+    /// html index.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, String>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
 }
 
 #[derive(Debug)]
@@ -36,7 +107,7 @@ pub struct LoadedIndex {
     pub rows: Vec<Vec<LoadedGlyph>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LoadedGlyph {
     pub glyph: IndexGlyph,
     pub image: RgbaImage,
@@ -69,7 +140,15 @@ body
 </head>
 "#,
         );
-        let _ = writeln!(s, "<div data-atlas>");
+        match self.size {
+            Some((width, height)) => {
+                /* tl doesn't see attributes after one without a value, so data-atlas goes last */
+                let _ = writeln!(s, "<div data-size={width}x{height} data-atlas>");
+            }
+            None => {
+                let _ = writeln!(s, "<div data-atlas>");
+            }
+        }
 
         for row in self.rows.iter() {
             let _ = writeln!(s, "  <div data-atlas=row>");
@@ -85,9 +164,7 @@ body
         s
     }
 
-    pub fn patch_html(&self, html: &str) -> Result<(usize, String)> {
-        let patch;
-
+    pub fn patch_html(&self, html: &str) -> Result<(usize, String), ManifestError> {
         let mut dom = tl::parse(html, tl::ParserOptions::default())?;
 
         let mut images = dom
@@ -97,9 +174,9 @@ body
                 Some((src, img))
             })
             .collect::<Vec<(&str, tl::NodeHandle)>>();
-        images.sort_by(|(a, _), (b, _)| a.cmp(b));
+        images.sort_by_key(|&(src, _)| src);
 
-        patch = self
+        let patch = self
             .rows
             .iter()
             .flat_map(|row| row.iter())
@@ -111,36 +188,102 @@ body
                     .binary_search_by(|&(src, _)| src.cmp(glyph.path.as_str()))
                     .ok()
                     .and_then(|index| images.get(index).cloned())
-                    .map(|(_, node)| (node, glyph.to_img_tag().to_string()))
+                    .map(|(_, node)| {
+                        /* keep the labels and things already in the html */
+                        let mut patched = dom
+                            .tag(node)
+                            .map(IndexGlyph::from_img_tag)
+                            .transpose()?
+                            .unwrap_or_else(|| glyph.clone());
+                        patched.patch_from(glyph);
+                        let img = patched.to_img_tag().to_string();
+                        Ok((node, img))
+                    })
             })
-            .collect::<Vec<(tl::NodeHandle, String)>>();
+            .collect::<Result<Vec<(tl::NodeHandle, String)>, ManifestError>>()?;
+
+        let matched = patch.len();
+
+        /* the size of the atlas it was just unpacked from, for --keep-layout */
+        let atlas = dom.query_selector_unchecked("div").find(|&div| {
+            dom.tag(div).is_some_and(|div| {
+                matches!(div.attributes().get("data-atlas"), Some(None))
+                    || div.attribute_value("data-atlas") == Some("")
+            })
+        });
+        if let (Some((width, height)), Some(atlas)) = (self.size, atlas) {
+            let size = tl::Bytes::try_from(format!("{width}x{height}"));
+            if let (Some(tl::Node::Tag(atlas)), Ok(size)) = (dom.node_mut(atlas), size) {
+                let attributes = atlas.attributes_mut();
+                /* tl writes attributes in the order they were first seen, so give data-atlas a
+                 * value for data-size to still be seen after it, see to_html() */
+                attributes.insert("data-atlas", Some(""));
+                attributes.insert("data-size", Some(size));
+            }
+        }
 
-        for (node, glyph) in patch.iter() {
-            if let Some(node) = dom.node_mut(*node) {
-                *node = tl::Node::Raw(tl::Bytes::from(glyph.as_str()));
+        for (node, glyph) in patch.into_iter() {
+            if let (Some(node), Ok(bytes)) = (dom.node_mut(node), tl::Bytes::try_from(glyph)) {
+                *node = tl::Node::Raw(bytes);
             }
         }
 
-        Ok((patch.len(), dom.outer_html()))
+        Ok((matched, dom.outer_html()))
     }
 
-    pub fn from_html(s: &str) -> Result<Self> {
+    pub fn from_html(s: &str) -> Result<Self, ManifestError> {
         let dom = tl::parse(s, tl::ParserOptions::default())?;
         let parser = dom.parser();
-        dom.query_selector_unchecked("[data-atlas=row]")
+        let rows = dom
+            .query_selector_unchecked("[data-atlas=row]")
             .filter_map(|row| dom.tag(row))
             .map(|row| {
                 row.query_selector(parser, "img[src]")
                     .expect("img[src]")
                     .filter_map(|img| dom.tag(img))
                     .map(IndexGlyph::from_img_tag)
-                    .collect::<Result<Vec<_>>>()
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let size = dom
+            .query_selector_unchecked("div")
+            .find_map(|div| dom.tag(div)?.attribute_value("data-size"))
+            .map(|s| {
+                s.split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .ok_or_else(|| ManifestError::Attribute {
+                        expected: "data-size=WIDTHxHEIGHT",
+                        found: s.to_owned(),
+                    })
+            })
+            .transpose()?;
+
+        Ok(Index { rows, size })
+    }
+
+    /// Pair each glyph with the image that `image` gives for its path, without touching the
+    /// filesystem like `load_images` does.
+    pub fn with_images(
+        self,
+        mut image: impl FnMut(&str) -> Option<RgbaImage>,
+    ) -> Result<LoadedIndex, Error> {
+        let rows = self
+            .rows
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|glyph| match image(&glyph.path) {
+                        Some(image) => Ok(LoadedGlyph { glyph, image }),
+                        None => Err(Error::MissingImage { path: glyph.path }),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
             })
-            .collect::<Result<Vec<_>>>()
-            .map(|rows| Index { rows })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(LoadedIndex { rows })
     }
 
-    pub fn load_images<P: AsRef<Path>>(self, root: P) -> Result<LoadedIndex> {
+    pub fn load_images<P: AsRef<Path>>(self, root: P) -> Result<LoadedIndex, Error> {
         let rows = self
             .rows
             .into_iter()
@@ -149,13 +292,17 @@ body
                     .map(|glyph| {
                         let glyph_path = root.as_ref().join(&glyph.path);
                         let image = image::open(&glyph_path)
-                            .with_context(|| format!("open {}", glyph_path.display()))?
+                            .map_err(|source| Error::ImageFile {
+                                action: "open",
+                                path: glyph_path,
+                                source,
+                            })?
                             .into_rgba8();
                         Ok(LoadedGlyph { glyph, image })
                     })
-                    .collect::<Result<Vec<_>>>()
+                    .collect::<Result<Vec<_>, Error>>()
             })
-            .collect::<Result<Vec<_>>>()?;
+            .collect::<Result<Vec<_>, _>>()?;
         Ok(LoadedIndex { rows })
     }
 }
@@ -170,36 +317,178 @@ impl IndexGlyph {
             fn fmt(&self, s: &mut fmt::Formatter<'_>) -> fmt::Result {
                 write!(s, "<img src='")?;
                 write_html_encoded_attribute_value(s, &self.0.path)?;
+                write!(s, "'")?;
                 if self.0.descent > 0 {
-                    write!(s, "' data-descent={}>", self.0.descent)
-                } else {
-                    write!(s, "'>")
+                    write!(s, " data-descent={}", self.0.descent)?;
+                }
+                if let Some(c) = self.0.char {
+                    write!(s, " data-char='")?;
+                    write_html_encoded_attribute_value(s, c.encode_utf8(&mut [0; 4]))?;
+                    write!(s, "'")?;
+                }
+                if let Some(name) = &self.0.name {
+                    write!(s, " data-name='")?;
+                    write_html_encoded_attribute_value(s, name)?;
+                    write!(s, "'")?;
                 }
+                if !self.0.tags.is_empty() {
+                    write!(s, " data-tags='")?;
+                    write_html_encoded_attribute_value(s, self.0.tags.join(" "))?;
+                    write!(s, "'")?;
+                }
+                if let Some((x, y)) = self.0.at {
+                    write!(s, " data-at={x},{y}")?;
+                }
+                if let Some(title) = self.0.title() {
+                    /* so hovering over the image in a browser says what it is */
+                    write!(s, " title='")?;
+                    write_html_encoded_attribute_value(s, title)?;
+                    write!(s, "'")?;
+                }
+                for (key, value) in self.0.meta.iter() {
+                    /* can't encode attribute names, so skip anything that wouldn't parse back,
+                     * and anything that would clash with the attributes above */
+                    if !is_meta_key(key) || RESERVED_DATA_KEYS.contains(&key.as_str()) {
+                        continue;
+                    }
+                    write!(s, " data-{key}='")?;
+                    write_html_encoded_attribute_value(s, value)?;
+                    write!(s, "'")?;
+                }
+                write!(s, ">")
             }
         }
     }
 
-    fn from_img_tag(img: &tl::HTMLTag<'_>) -> Result<Self> {
+    fn from_img_tag(img: &tl::HTMLTag<'_>) -> Result<Self, ManifestError> {
         let path = img
             .attribute_value("src")
-            .context("img missing src attribute?")?
+            .ok_or(ManifestError::MissingSrc)?
             .to_owned();
 
         let descent = img
             .attribute_value("data-descent")
             .map(|s| {
-                s.parse()
-                    .with_context(|| format!("expected non-negative integer, found: {s}"))
+                s.parse().map_err(|_| ManifestError::Attribute {
+                    expected: "non-negative integer",
+                    found: s.to_owned(),
+                })
             })
             .transpose()?
             .unwrap_or(0);
 
-        Ok(IndexGlyph { path, descent })
+        let char = img
+            .attribute_value("data-char")
+            .map(|s| {
+                let s = html_decoded(s);
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Ok(c),
+                    _ => Err(ManifestError::Attribute {
+                        expected: "one character",
+                        found: s,
+                    }),
+                }
+            })
+            .transpose()?;
+
+        let name = img.attribute_value("data-name").map(html_decoded);
+
+        let tags = img
+            .attribute_value("data-tags")
+            .map(|s| {
+                html_decoded(s)
+                    .split_whitespace()
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let at = img
+            .attribute_value("data-at")
+            .map(|s| {
+                s.split_once(',')
+                    .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)))
+                    .ok_or_else(|| ManifestError::Attribute {
+                        expected: "data-at=X,Y",
+                        found: s.to_owned(),
+                    })
+            })
+            .transpose()?;
+
+        let meta = img
+            .attributes()
+            .iter()
+            .filter_map(|(key, value)| {
+                let key = key.strip_prefix("data-")?;
+                if RESERVED_DATA_KEYS.contains(&key) || !is_meta_key(key) {
+                    return None;
+                }
+                Some((key.to_owned(), html_decoded(&value.unwrap_or_default())))
+            })
+            .collect();
+
+        Ok(IndexGlyph {
+            path,
+            descent,
+            char,
+            name,
+            tags,
+            at,
+            meta,
+        })
+    }
+
+    /// Take the descent and position from `other`, which are what change when unpacking over
+    /// an existing index, and any labels that `self` doesn't already have.
+    pub fn patch_from(&mut self, other: &IndexGlyph) {
+        self.descent = other.descent;
+        self.at = other.at;
+        self.char = self.char.or(other.char);
+        if self.name.is_none() {
+            self.name.clone_from(&other.name);
+        }
+        if self.tags.is_empty() {
+            self.tags.clone_from(&other.tags);
+        }
+        for (key, value) in other.meta.iter() {
+            self.meta
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+
+    /// Something to call this glyph by in a listing, like `gold` or `Z`.
+    pub fn title(&self) -> Option<String> {
+        match (&self.name, self.char) {
+            (Some(name), _) => Some(name.clone()),
+            (None, Some(c)) => Some(c.to_string()),
+            (None, None) => None,
+        }
+    }
+
+    /// What identifies a glyph between versions of an atlas when they're compared.
+    pub fn key(&self) -> String {
+        match (&self.name, self.char) {
+            (Some(name), _) => format!("name:{name}"),
+            (None, Some(c)) => format!("char:{c}"),
+            (None, None) => format!("path:{}", self.path),
+        }
     }
 }
 
 impl LoadedIndex {
-    pub fn to_atlas_image(&self, (width, height): (u32, u32)) -> Result<RgbaImage> {
+    /// Pack rows left to right, top to bottom, in an atlas of this size. A zero width or height
+    /// is just big enough. Fails if any glyph doesn't fit, see `flow_layout` and `clipped`.
+    pub fn to_atlas_image(&self, size: (u32, u32)) -> Result<RgbaImage, Error> {
+        let layout = self.flow_layout(size)?;
+        self.refuse_clipping(&layout)?;
+        Ok(self.draw(&layout))
+    }
+
+    /// Where `to_atlas_image` puts each glyph: each row starts under the tallest glyph of the row
+    /// before, and each glyph goes right after the one before it.
+    pub fn flow_layout(&self, (width, height): (u32, u32)) -> Result<Layout, Error> {
         let width = match width {
             0 => self.widest_row_width().unwrap_or(0),
             _ => width,
@@ -209,37 +498,266 @@ impl LoadedIndex {
             0 => row_heights.iter().sum(),
             _ => height,
         };
+        check_dimensions(width, height)?;
 
-        if width == 0 || height == 0 {
-            return Err(anyhow::anyhow!(
-                "cowardly refusing to make an image with dimensions {width}x{height}"
-            ));
-        }
+        let mut y = 0u32;
+        let positions = self
+            .rows
+            .iter()
+            .zip(row_heights)
+            .map(|(row, row_height)| {
+                let mut x = 0u32;
+                let row_positions = row
+                    .iter()
+                    .map(|loaded| {
+                        let point = Point { x, y };
+                        x = x.saturating_add(loaded.image.width() + FRAME_WIDTH);
+                        point
+                    })
+                    .collect();
+                y = y.saturating_add(row_height);
+                row_positions
+            })
+            .collect();
+
+        Ok(Layout {
+            size: (width, height),
+            positions,
+        })
+    }
+
+    /// Like `flow_layout`, but glyphs that remember where they were unpacked from go back there
+    /// if they still fit without overlapping anything, so an atlas packs to the same pixels it
+    /// was unpacked from. Other glyphs go after the glyph before them in their row, or under the
+    /// row before if they're first, moving right until there's room.
+    pub fn kept_layout(&self, (width, height): (u32, u32)) -> Result<Layout, Error> {
+        let limit = |dim: u32| if dim == 0 { u32::MAX } else { dim };
+        let (max_width, max_height) = (limit(width), limit(height));
+
+        /* each placed glyph with its frame as (x, y, w, h) */
+        let mut placed = Vec::<(u32, u32, u32, u32)>::new();
+        let overlap = |placed: &[(u32, u32, u32, u32)], (x, y, w, h): (u32, u32, u32, u32)| {
+            placed
+                .iter()
+                .find(|&&(px, py, pw, ph)| {
+                    x < px.saturating_add(pw)
+                        && px < x.saturating_add(w)
+                        && y < py.saturating_add(ph)
+                        && py < y.saturating_add(h)
+                })
+                .map(|&(px, _, pw, _)| px.saturating_add(pw))
+        };
+        let inside = |(x, y, w, h): (u32, u32, u32, u32)| {
+            x.checked_add(w).is_some_and(|right| right <= max_width)
+                && y.checked_add(h).is_some_and(|bottom| bottom <= max_height)
+        };
 
-        let mut atlas = RgbaImage::new(width, height);
-
-        atlas.pixels_mut().for_each(|p| *p = FRAME);
-
-        row_heights.into_iter().zip(self.rows.iter()).try_fold(
-            Point { x: 0, y: 0 },
-            |point, (row_height, row)| {
-                row.iter().try_fold(point, |point, loaded| {
-                    let LoadedGlyph { image, glyph } = loaded;
-                    copy_glyph_to_atlas(&mut atlas, point, image, glyph.descent);
-                    image
-                        .width()
-                        .checked_add(FRAME_WIDTH)
-                        .and_then(|dx| point.x.checked_add(dx))
-                        .map(|x| Point { x, y: point.y })
+        let mut positions = Vec::with_capacity(self.rows.len());
+        let mut row_top = 0;
+        for row in &self.rows {
+            let mut row_rects = Vec::<(u32, u32, u32, u32)>::with_capacity(row.len());
+            for loaded in row {
+                let (w, h) = (
+                    loaded.image.width() + FRAME_WIDTH,
+                    loaded.image.height() + FRAME_WIDTH,
+                );
+                let recorded = loaded
+                    .glyph
+                    .at
+                    .map(|(x, y)| (x, y, w, h))
+                    .filter(|&rect| inside(rect) && overlap(&placed, rect).is_none());
+                let rect = recorded.unwrap_or_else(|| {
+                    let mut rect = match row_rects.last() {
+                        Some(&(x, y, w_before, _)) => (x.saturating_add(w_before), y, w, h),
+                        None => (0, row_top, w, h),
+                    };
+                    /* if this runs off the edge, it's reported by `clipped` */
+                    while let Some(right) = overlap(&placed, rect) {
+                        rect.0 = right;
+                    }
+                    rect
                 });
-                point.checked_add(Point {
-                    x: 0,
-                    y: row_height,
+                placed.push(rect);
+                row_rects.push(rect);
+            }
+            row_top = row_rects
+                .iter()
+                .map(|&(_, y, _, h)| y.saturating_add(h))
+                .max()
+                .unwrap_or(row_top);
+            positions.push(
+                row_rects
+                    .into_iter()
+                    .map(|(x, y, _, _)| Point { x, y })
+                    .collect(),
+            );
+        }
+
+        /* the far edge of every glyph, which a data-at near u32::MAX can push past it */
+        let extent = |end: fn(&(u32, u32, u32, u32)) -> Option<u32>| {
+            placed
+                .iter()
+                .map(end)
+                .try_fold(0, |max, end| Some(max.max(end?)))
+        };
+        let width = match width {
+            0 => extent(|&(x, _, w, _)| x.checked_add(w)),
+            _ => Some(width),
+        };
+        let height = match height {
+            0 => extent(|&(_, y, _, h)| y.checked_add(h)),
+            _ => Some(height),
+        };
+        let (Some(width), Some(height)) = (width, height) else {
+            return Err(Error::Size {
+                width: width.unwrap_or(u32::MAX),
+                height: height.unwrap_or(u32::MAX),
+            });
+        };
+        check_dimensions(width, height)?;
+
+        Ok(Layout {
+            size: (width, height),
+            positions,
+        })
+    }
+
+    /// Every glyph that, with its frame, doesn't fit inside the atlas at its place in `layout`.
+    pub fn clipped(&self, layout: &Layout) -> Vec<Clipped> {
+        let (width, height) = layout.size;
+        self.rows
+            .iter()
+            .zip(&layout.positions)
+            .enumerate()
+            .flat_map(|(row, (glyphs, positions))| {
+                glyphs
+                    .iter()
+                    .zip(positions)
+                    .filter(move |(loaded, at)| {
+                        let right = at.x.saturating_add(loaded.image.width() + FRAME_WIDTH);
+                        let bottom = at.y.saturating_add(loaded.image.height() + FRAME_WIDTH);
+                        right > width || bottom > height
+                    })
+                    .map(move |(loaded, &at)| Clipped {
+                        path: loaded.glyph.path.clone(),
+                        row,
+                        at,
+                        size: loaded.image.dimensions(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Draw the atlas for `layout`. Glyphs that are `clipped` are cut off at the edges.
+    pub fn draw(&self, layout: &Layout) -> RgbaImage {
+        let (width, height) = layout.size;
+        let mut atlas = RgbaImage::from_pixel(width, height, FRAME);
+        for (row, positions) in self.rows.iter().zip(&layout.positions) {
+            for (loaded, &at) in row.iter().zip(positions) {
+                copy_glyph_to_atlas(&mut atlas, at, &loaded.image, loaded.glyph.descent);
+            }
+        }
+        atlas
+    }
+
+    fn refuse_clipping(&self, layout: &Layout) -> Result<(), Error> {
+        let clipped = self.clipped(layout);
+        if !clipped.is_empty() {
+            return Err(Error::Clipped(clipped));
+        }
+        Ok(())
+    }
+
+    /// Break rows wider than `width` onto new rows right after them, keeping every glyph in the
+    /// same order. Returns how many rows were broken up, or an error if a glyph is wider than
+    /// `width` on its own.
+    pub fn wrap_rows(&mut self, width: u32) -> Result<usize, Error> {
+        let mut wrapped = 0;
+        let mut rows = Vec::with_capacity(self.rows.len());
+        for (r, row) in std::mem::take(&mut self.rows).into_iter().enumerate() {
+            let mut line = Vec::new();
+            let mut x = 0;
+            let mut broken = false;
+            for loaded in row {
+                let w = loaded.image.width() + FRAME_WIDTH;
+                if w > width {
+                    return Err(Error::TooWide {
+                        path: loaded.glyph.path,
+                        row: r,
+                        width: w,
+                        wrap: width,
+                    });
+                }
+                if x + w > width {
+                    rows.push(std::mem::take(&mut line));
+                    x = 0;
+                    broken = true;
+                }
+                x += w;
+                line.push(loaded);
+            }
+            rows.push(line);
+            wrapped += broken as usize;
+        }
+        self.rows = rows;
+        Ok(wrapped)
+    }
+
+    /// Write each glyph's image to its path under `root`.
+    pub fn save_images<P: AsRef<Path>>(&self, root: P) -> Result<(), Error> {
+        self.rows
+            .iter()
+            .flat_map(|row| row.iter())
+            .try_for_each(|LoadedGlyph { glyph, image }| {
+                let glyph_path = root.as_ref().join(&glyph.path);
+                image.save(&glyph_path).map_err(|source| Error::ImageFile {
+                    action: "save",
+                    path: glyph_path,
+                    source,
                 })
-            },
-        );
+            })
+    }
+
+    /// Give glyphs a path of their own where two different images have the same path, like after
+    /// combining glyphs from more than one directory. Paths in `reserved` aren't used either.
+    pub fn rename_duplicate_paths(&mut self, reserved: &[String]) {
+        let mut taken = HashMap::<String, &RgbaImage>::new();
+        let mut renames = Vec::new();
+        for (r, row) in self.rows.iter().enumerate() {
+            for (c, loaded) in row.iter().enumerate() {
+                match taken.get(&loaded.glyph.path) {
+                    Some(&image) if image != &loaded.image => renames.push((r, c)),
+                    Some(_) => (),
+                    None => {
+                        taken.insert(loaded.glyph.path.clone(), &loaded.image);
+                    }
+                }
+            }
+        }
+        let mut taken = taken.into_keys().collect::<Vec<_>>();
+        taken.extend_from_slice(reserved);
+        for (r, c) in renames {
+            let path = unused_path(&self.rows[r][c].glyph.path, "", &taken);
+            taken.push(path.clone());
+            self.rows[r][c].glyph.path = path;
+        }
+    }
+
+    pub fn to_index(&self) -> Index {
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|loaded| loaded.glyph.clone()).collect())
+            .collect();
+        Index { rows, size: None }
+    }
 
-        Ok(atlas)
+    /// The size of the atlas when `to_atlas_image` is given zeroes.
+    pub fn packed_size(&self) -> (u32, u32) {
+        (
+            self.widest_row_width().unwrap_or(0),
+            self.row_heights().iter().sum(),
+        )
     }
 
     fn widest_row_width(&self) -> Option<u32> {
@@ -266,7 +784,14 @@ impl LoadedIndex {
     }
 }
 
-fn copy_glyph_to_atlas(
+fn check_dimensions(width: u32, height: u32) -> Result<(), Error> {
+    if width == 0 || height == 0 {
+        return Err(Error::Size { width, height });
+    }
+    Ok(())
+}
+
+pub fn copy_glyph_to_atlas(
     atlas: &mut RgbaImage,
     topleft: Point,
     glyph_image: &RgbaImage,
@@ -277,13 +802,15 @@ fn copy_glyph_to_atlas(
     image::imageops::replace(atlas, glyph_image, topleft.x as i64, topleft.y as i64);
 
     if descent > 0 {
-        topleft
+        if let Some(p) = topleft
             .checked_add(Point {
                 x: glyph_image.width(),
                 y: glyph_image.height().saturating_sub(descent),
             })
             .and_then(|p| atlas.get_pixel_mut_checked(p.x, p.y))
-            .map(|p| *p = BASELINE);
+        {
+            *p = BASELINE;
+        }
     }
 }
 
@@ -303,6 +830,51 @@ where
     Ok(())
 }
 
+/// `data-*` attributes that are fields of `IndexGlyph` instead of `meta`.
+const RESERVED_DATA_KEYS: &[&str] = &["descent", "char", "name", "tags", "at"];
+
+fn is_meta_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+fn html_decoded(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_owned();
+    }
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+/// `path` with `suffix` after its file stem, and a number too if that's in `taken`.
+pub fn unused_path(path: &str, suffix: &str, taken: &[String]) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("glyph");
+    let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("png");
+    let with = |name: String| match path.parent().and_then(|p| p.to_str()) {
+        Some(parent) if !parent.is_empty() => format!("{parent}/{name}"),
+        _ => name,
+    };
+    (1..)
+        .map(|n| match n {
+            1 if !suffix.is_empty() => with(format!("{stem}{suffix}.{ext}")),
+            n => with(format!("{stem}{suffix}-{n}.{ext}")),
+        })
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap_or_default()
+}
+
+pub fn html_encoded(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    let _ = write_html_encoded_attribute_value(&mut encoded, s);
+    encoded
+}
+
 fn html_encoded_char(c: char) -> Option<&'static str> {
     Some(match c {
         '&' => "&amp;",
@@ -357,3 +929,67 @@ impl<'buf> DomTag<'buf> for tl::HTMLTag<'buf> {
         self.attributes().get(attribute)??.try_as_utf8_str()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_rows() {
+        let glyph = |path: &str, w: u32| LoadedGlyph {
+            glyph: IndexGlyph {
+                path: path.into(),
+                ..IndexGlyph::default()
+            },
+            image: RgbaImage::new(w, 2),
+        };
+        let mut loaded = LoadedIndex {
+            rows: vec![
+                vec![glyph("a", 3), glyph("b", 3), glyph("c", 1), glyph("d", 4)],
+                vec![glyph("e", 2)],
+            ],
+        };
+        assert_eq!(loaded.wrap_rows(10).unwrap(), 1);
+        let paths = loaded
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|l| l.glyph.path.as_str())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [vec!["a", "b", "c"], vec!["d"], vec!["e"]]);
+        assert_eq!(loaded.packed_size(), (10, 9));
+        assert!(loaded.wrap_rows(4).is_err());
+    }
+
+    #[test]
+    fn test_kept_layout_far_away() {
+        let glyph = |at| LoadedGlyph {
+            glyph: IndexGlyph {
+                at,
+                ..IndexGlyph::default()
+            },
+            image: RgbaImage::new(3, 2),
+        };
+        let loaded = LoadedIndex {
+            rows: vec![vec![glyph(Some((u32::MAX, 0)))]],
+        };
+        let layout = loaded.kept_layout((0, 0)).unwrap();
+        assert_eq!(layout.positions, [vec![Point { x: 0, y: 0 }]]);
+
+        /* the first fits right up to the edge, so the next one can't */
+        let w = 3 + FRAME_WIDTH;
+        let loaded = LoadedIndex {
+            rows: vec![vec![glyph(Some((u32::MAX - w, 0))), glyph(None)]],
+        };
+        assert!(matches!(
+            loaded.kept_layout((0, 0)),
+            Err(Error::Size {
+                width: u32::MAX,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,513 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::index::{Index, IndexGlyph};
+use crate::render::DEFAULT_TEXT;
+
+/// A group of glyphs in GameFont.tga that the game treats the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Text,
+    Yield,
+    Commerce,
+    /// Religions and corporations share a row, each followed by its holy city or headquarters.
+    Religion,
+    Bonus,
+    Symbol,
+}
+
+impl Category {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::Text => "text",
+            Category::Yield => "yield",
+            Category::Commerce => "commerce",
+            Category::Religion => "religion",
+            Category::Bonus => "bonus",
+            Category::Symbol => "symbol",
+        }
+    }
+}
+
+/// A glyph in the stock layout.
+#[derive(Debug, Clone, Copy)]
+pub struct StockGlyph {
+    /// used for the file name when unpacking, like `yield_food`
+    pub name: &'static str,
+    pub tags: &'static [&'static str],
+    /// what the game calls it in text, like `ICON_FOOD` for `[ICON_FOOD]`
+    pub symbol: Option<&'static str>,
+}
+
+/// A row of icons in the stock layout.
+#[derive(Debug, Clone, Copy)]
+pub struct StockRow {
+    pub category: Category,
+    pub glyphs: &'static [StockGlyph],
+}
+
+macro_rules! glyph {
+    ($name:literal, [$($tag:literal),*]) => {
+        StockGlyph { name: $name, tags: &[$($tag),*], symbol: None }
+    };
+    ($name:literal, [$($tag:literal),*], $symbol:literal) => {
+        StockGlyph { name: $name, tags: &[$($tag),*], symbol: Some($symbol) }
+    };
+}
+
+/// The icon rows of Beyond the Sword's GameFont.tga, after the text rows in `DEFAULT_TEXT`.
+///
+/// The order follows `CvGameTextMgr::assignFontIds()` in the SDK, which is the order of the
+/// XML info files. Bonuses are in the order of their `FontButtonIndex`, the first glyph in that
+/// row is the generic resource icon at index zero.
+pub const BTS_ICON_ROWS: &[StockRow] = &[
+    StockRow {
+        category: Category::Yield,
+        glyphs: &[
+            glyph!("yield_food", ["yield"], "ICON_FOOD"),
+            glyph!("yield_production", ["yield"], "ICON_PRODUCTION"),
+            glyph!("yield_commerce", ["yield"], "ICON_COMMERCE"),
+        ],
+    },
+    StockRow {
+        category: Category::Commerce,
+        glyphs: &[
+            glyph!("commerce_gold", ["commerce"], "ICON_GOLD"),
+            glyph!("commerce_research", ["commerce"], "ICON_RESEARCH"),
+            glyph!("commerce_culture", ["commerce"], "ICON_CULTURE"),
+            glyph!("commerce_espionage", ["commerce"], "ICON_ESPIONAGE"),
+        ],
+    },
+    StockRow {
+        category: Category::Religion,
+        glyphs: &[
+            glyph!("religion_judaism", ["religion"]),
+            glyph!("religion_judaism_holy_city", ["religion", "holy_city"]),
+            glyph!("religion_christianity", ["religion"]),
+            glyph!("religion_christianity_holy_city", ["religion", "holy_city"]),
+            glyph!("religion_islam", ["religion"]),
+            glyph!("religion_islam_holy_city", ["religion", "holy_city"]),
+            glyph!("religion_hinduism", ["religion"]),
+            glyph!("religion_hinduism_holy_city", ["religion", "holy_city"]),
+            glyph!("religion_buddhism", ["religion"]),
+            glyph!("religion_buddhism_holy_city", ["religion", "holy_city"]),
+            glyph!("religion_confucianism", ["religion"]),
+            glyph!("religion_confucianism_holy_city", ["religion", "holy_city"]),
+            glyph!("religion_taoism", ["religion"]),
+            glyph!("religion_taoism_holy_city", ["religion", "holy_city"]),
+            glyph!("corporation_cereal_mills", ["corporation"]),
+            glyph!(
+                "corporation_cereal_mills_hq",
+                ["corporation", "headquarters"]
+            ),
+            glyph!("corporation_sids_sushi", ["corporation"]),
+            glyph!("corporation_sids_sushi_hq", ["corporation", "headquarters"]),
+            glyph!("corporation_standard_ethanol", ["corporation"]),
+            glyph!(
+                "corporation_standard_ethanol_hq",
+                ["corporation", "headquarters"]
+            ),
+            glyph!("corporation_creative_constructions", ["corporation"]),
+            glyph!(
+                "corporation_creative_constructions_hq",
+                ["corporation", "headquarters"]
+            ),
+            glyph!("corporation_mining_inc", ["corporation"]),
+            glyph!("corporation_mining_inc_hq", ["corporation", "headquarters"]),
+            glyph!("corporation_aluminum_co", ["corporation"]),
+            glyph!(
+                "corporation_aluminum_co_hq",
+                ["corporation", "headquarters"]
+            ),
+            glyph!("corporation_civilized_jewelers", ["corporation"]),
+            glyph!(
+                "corporation_civilized_jewelers_hq",
+                ["corporation", "headquarters"]
+            ),
+        ],
+    },
+    StockRow {
+        category: Category::Bonus,
+        glyphs: &[
+            glyph!("bonus", ["bonus"]),
+            glyph!("bonus_aluminum", ["bonus", "strategic"]),
+            glyph!("bonus_coal", ["bonus", "strategic"]),
+            glyph!("bonus_copper", ["bonus", "strategic"]),
+            glyph!("bonus_horse", ["bonus", "strategic"]),
+            glyph!("bonus_iron", ["bonus", "strategic"]),
+            glyph!("bonus_marble", ["bonus", "strategic"]),
+            glyph!("bonus_oil", ["bonus", "strategic"]),
+            glyph!("bonus_stone", ["bonus", "strategic"]),
+            glyph!("bonus_uranium", ["bonus", "strategic"]),
+            glyph!("bonus_banana", ["bonus", "food"]),
+            glyph!("bonus_clam", ["bonus", "food"]),
+            glyph!("bonus_corn", ["bonus", "food"]),
+            glyph!("bonus_cow", ["bonus", "food"]),
+            glyph!("bonus_crab", ["bonus", "food"]),
+            glyph!("bonus_deer", ["bonus", "food"]),
+            glyph!("bonus_fish", ["bonus", "food"]),
+            glyph!("bonus_pig", ["bonus", "food"]),
+            glyph!("bonus_rice", ["bonus", "food"]),
+            glyph!("bonus_sheep", ["bonus", "food"]),
+            glyph!("bonus_wheat", ["bonus", "food"]),
+            glyph!("bonus_dye", ["bonus", "luxury"]),
+            glyph!("bonus_fur", ["bonus", "luxury"]),
+            glyph!("bonus_gems", ["bonus", "luxury"]),
+            glyph!("bonus_gold", ["bonus", "luxury"]),
+            glyph!("bonus_incense", ["bonus", "luxury"]),
+            glyph!("bonus_ivory", ["bonus", "luxury"]),
+            glyph!("bonus_silk", ["bonus", "luxury"]),
+            glyph!("bonus_silver", ["bonus", "luxury"]),
+            glyph!("bonus_spices", ["bonus", "luxury"]),
+            glyph!("bonus_sugar", ["bonus", "luxury"]),
+            glyph!("bonus_wine", ["bonus", "luxury"]),
+            glyph!("bonus_whale", ["bonus", "luxury"]),
+            glyph!("bonus_drama", ["bonus", "culture"]),
+            glyph!("bonus_music", ["bonus", "culture"]),
+            glyph!("bonus_movies", ["bonus", "culture"]),
+        ],
+    },
+    StockRow {
+        category: Category::Symbol,
+        /* FontSymbols in CvEnums.h */
+        glyphs: &[
+            glyph!("symbol_happy", ["symbol"], "ICON_HAPPY"),
+            glyph!("symbol_unhappy", ["symbol"], "ICON_UNHAPPY"),
+            glyph!("symbol_healthy", ["symbol"], "ICON_HEALTHY"),
+            glyph!("symbol_unhealthy", ["symbol"], "ICON_UNHEALTHY"),
+            glyph!("symbol_bullet", ["symbol"], "ICON_BULLET"),
+            glyph!("symbol_strength", ["symbol"], "ICON_STRENGTH"),
+            glyph!("symbol_moves", ["symbol"], "ICON_MOVES"),
+            glyph!("symbol_religion", ["symbol"], "ICON_RELIGION"),
+            glyph!("symbol_star", ["symbol"], "ICON_STAR"),
+            glyph!("symbol_silver_star", ["symbol"], "ICON_SILVER_STAR"),
+            glyph!("symbol_trade", ["symbol"], "ICON_TRADE"),
+            glyph!("symbol_defense", ["symbol"], "ICON_DEFENSE"),
+            glyph!("symbol_great_people", ["symbol"], "ICON_GREATPEOPLE"),
+            glyph!("symbol_bad_gold", ["symbol"], "ICON_BAD_GOLD"),
+            glyph!("symbol_bad_food", ["symbol"], "ICON_BAD_FOOD"),
+            glyph!("symbol_eaten_food", ["symbol"], "ICON_EATEN_FOOD"),
+            glyph!("symbol_golden_age", ["symbol"], "ICON_GOLDEN_AGE"),
+            glyph!("symbol_angry_pop", ["symbol"], "ICON_ANGRY_POP"),
+            glyph!("symbol_open_borders", ["symbol"], "ICON_OPEN_BORDERS"),
+            glyph!("symbol_defensive_pact", ["symbol"], "ICON_DEFENSIVE_PACT"),
+            glyph!("symbol_map", ["symbol"], "ICON_MAP"),
+            glyph!("symbol_occupation", ["symbol"], "ICON_OCCUPATION"),
+            glyph!("symbol_power", ["symbol"], "ICON_POWER"),
+            glyph!("symbol_citizen", ["symbol"], "ICON_CITIZEN"),
+            glyph!("symbol_great_general", ["symbol"], "ICON_GREATGENERAL"),
+            glyph!("symbol_airport", ["symbol"], "ICON_AIRPORT"),
+        ],
+    },
+];
+
+/// Glyph counts of the text rows, one row per line of `DEFAULT_TEXT`.
+pub fn text_row_lengths() -> Vec<usize> {
+    DEFAULT_TEXT
+        .lines()
+        .map(|line| line.chars().count())
+        .collect()
+}
+
+/// What the game calls a glyph. Text is looked up by character, icons by a number past the last
+/// character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameId {
+    Text(char),
+    Icon(u32),
+    /// in the text rows but past the end of `DEFAULT_TEXT`, so the game never uses it
+    Unused,
+}
+
+impl GameId {
+    pub fn id(self) -> Option<u32> {
+        match self {
+            GameId::Text(c) => Some(c as u32),
+            GameId::Icon(id) => Some(id),
+            GameId::Unused => None,
+        }
+    }
+}
+
+impl fmt::Display for GameId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.id() {
+            Some(id) => f.pad(&id.to_string()),
+            None => f.pad("-"),
+        }
+    }
+}
+
+/// How the game numbers glyphs, following `CvGameTextMgr::assignFontIds()` in the SDK with one
+/// row of the atlas for each kind of icon.
+///
+/// The text rows hold the characters of `DEFAULT_TEXT` in order. Icons start at `first_icon_id`,
+/// right after ™. Every row after that starts at the next multiple of `pad`, and rows other than
+/// the first with fewer than `pad` infos skip one more multiple, see `next_row_start`. For
+/// bonuses, the glyph's position in its row is its `FontButtonIndex`.
+#[derive(Debug, Clone, Copy)]
+pub struct IdScheme {
+    pub first_icon_id: u32,
+    pub pad: u32,
+}
+
+pub const BTS_IDS: IdScheme = IdScheme {
+    first_icon_id: 8483,
+    pad: 25,
+};
+
+/// How many of the icons that vary between mods are defined in XML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InfoCounts {
+    pub religions: usize,
+    pub corporations: usize,
+    /// not counting the generic resource icon
+    pub bonuses: usize,
+}
+
+pub const BTS_COUNTS: InfoCounts = InfoCounts {
+    religions: 7,
+    corporations: 7,
+    bonuses: 35,
+};
+
+impl IdScheme {
+    /// The game ID of every glyph in `index`, in the same rows.
+    ///
+    /// Rows are text until all of `DEFAULT_TEXT` is placed, so a text row that runs long pushes
+    /// the icons down a row instead of shifting them.
+    pub fn assign(&self, index: &Index) -> Vec<Vec<GameId>> {
+        let mut chars = DEFAULT_TEXT.chars().filter(|&c| c != '\n').peekable();
+        let mut next_icon = self.first_icon_id;
+        let mut icon_rows = 0;
+
+        index
+            .rows
+            .iter()
+            .map(|row| {
+                if chars.peek().is_some() {
+                    return row
+                        .iter()
+                        .map(|_| chars.next().map_or(GameId::Unused, GameId::Text))
+                        .collect();
+                }
+
+                let start = next_icon;
+                let len = row.len() as u32;
+                next_icon = self.next_row_start(icon_rows, start, len);
+                icon_rows += 1;
+                (start..start + len).map(GameId::Icon).collect()
+            })
+            .collect()
+    }
+
+    /// Where `assignFontIds()` starts each row of `BTS_ICON_ROWS` for a mod with `counts`.
+    pub fn expected_row_starts(&self, counts: &InfoCounts) -> Vec<u32> {
+        let religions = 2 * (counts.religions + counts.corporations) as u32;
+        let bonuses = 1 + counts.bonuses as u32;
+
+        let mut starts = vec![self.first_icon_id];
+        for (nth, len) in [3, 4, religions, bonuses].into_iter().enumerate() {
+            let start = starts[nth];
+            starts.push(self.next_row_start(nth, start, len));
+        }
+        starts
+    }
+
+    /// Where the icon row after the `nth` one starts, when that one starts at `start` and has
+    /// `len` glyphs. The extra pad after a short row counts infos, so the bonus row's generic
+    /// resource icon doesn't count, and the first row never gets one.
+    fn next_row_start(&self, nth: usize, start: u32, len: u32) -> u32 {
+        let infos = match BTS_ICON_ROWS.get(nth).map(|row| row.category) {
+            Some(Category::Bonus) => len.saturating_sub(1),
+            _ => len,
+        };
+        let next = self.pad_after(start + len);
+        match nth > 0 && infos < self.pad {
+            true => self.pad_after(next),
+            false => next,
+        }
+    }
+
+    /// Like the `do { ++id; } while (id % pad != 0)` loops in `assignFontIds()`.
+    fn pad_after(&self, id: u32) -> u32 {
+        (id / self.pad + 1) * self.pad
+    }
+}
+
+/// Which parts of an index look like the stock layout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Matched {
+    pub text: bool,
+    pub icons: bool,
+}
+
+/// Compare the row structure of `index` to the stock layout.
+///
+/// The text rows must have exactly the same number of glyphs as `DEFAULT_TEXT`. After those, if
+/// there are as many rows as `BTS_ICON_ROWS`, the icons are considered a match even if rows have
+/// more or fewer glyphs, since mods often add to the end of a row. An index of just the text rows,
+/// like the one from atlast.html, matches the text.
+pub fn match_stock(index: &Index) -> Matched {
+    let text_rows = text_row_lengths();
+    let text = index.rows.len() >= text_rows.len()
+        && index
+            .rows
+            .iter()
+            .zip(text_rows.iter())
+            .all(|(row, &len)| row.len() == len);
+    let icons = text && index.rows.len() == text_rows.len() + BTS_ICON_ROWS.len();
+    Matched { text, icons }
+}
+
+/// If `index` looks like the stock layout, label its glyphs with their character or name, tags,
+/// and game symbol. Icons are also given file names based on their names, like
+/// `yield_food.png`. Text glyphs keep their numbered names, so that unpacking a text atlas from
+/// atlast.html with `--patch-index` still finds them.
+///
+/// Glyphs past the end of a stock row are named by their category and position in the row.
+pub fn annotate_stock(index: &mut Index) -> Matched {
+    let matched = match_stock(index);
+    let text_rows = text_row_lengths().len();
+
+    if matched.text {
+        let chars = DEFAULT_TEXT.chars().filter(|&c| c != '\n');
+        index
+            .rows
+            .iter_mut()
+            .take(text_rows)
+            .flat_map(|row| row.iter_mut())
+            .zip(chars)
+            .for_each(|(glyph, c)| {
+                glyph.char = Some(c);
+                glyph.tags = vec![Category::Text.as_str().to_owned()];
+            });
+    }
+
+    if matched.icons {
+        for (row, stock) in index.rows.iter_mut().skip(text_rows).zip(BTS_ICON_ROWS) {
+            for (i, glyph) in row.iter_mut().enumerate() {
+                label_icon(glyph, stock, i);
+            }
+        }
+    }
+
+    matched
+}
+
+fn label_icon(glyph: &mut IndexGlyph, stock: &StockRow, i: usize) {
+    let (name, tags, symbol) = match stock.glyphs.get(i) {
+        Some(s) => (
+            s.name.to_owned(),
+            s.tags.iter().map(|&t| t.to_owned()).collect(),
+            s.symbol,
+        ),
+        None => (
+            format!("{}_{i:02}", stock.category.as_str()),
+            vec![stock.category.as_str().to_owned()],
+            None,
+        ),
+    };
+    glyph.path = format!("{name}.png");
+    glyph.name = Some(name);
+    glyph.tags = tags;
+    glyph.meta = BTreeMap::from_iter(symbol.map(|s| ("symbol".to_owned(), s.to_owned())));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(row_lengths: &[usize]) -> Index {
+        let mut i = 0;
+        let rows = row_lengths
+            .iter()
+            .map(|&len| {
+                (0..len)
+                    .map(|_| {
+                        i += 1;
+                        IndexGlyph {
+                            path: format!("{:03}.png", i - 1),
+                            ..IndexGlyph::default()
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Index { rows, size: None }
+    }
+
+    #[test]
+    fn test_annotate_stock() {
+        let mut lengths = text_row_lengths();
+        lengths.extend(BTS_ICON_ROWS.iter().map(|row| row.glyphs.len()));
+        /* a mod with an extra bonus */
+        lengths[7] += 1;
+
+        let mut index = numbered(&lengths);
+        assert_eq!(
+            annotate_stock(&mut index),
+            Matched {
+                text: true,
+                icons: true
+            }
+        );
+
+        assert_eq!(index.rows[0][32].path, "032.png");
+        assert_eq!(index.rows[0][32].char, Some('A'));
+        assert_eq!(index.rows[4][0].path, "yield_food.png");
+        assert_eq!(index.rows[4][0].meta["symbol"], "ICON_FOOD");
+        assert_eq!(index.rows[7][1].name.as_deref(), Some("bonus_aluminum"));
+        assert_eq!(index.rows[7][36].path, "bonus_36.png");
+
+        let mut text_only = numbered(&text_row_lengths());
+        assert_eq!(
+            annotate_stock(&mut text_only),
+            Matched {
+                text: true,
+                icons: false
+            }
+        );
+
+        let mut other = numbered(&[3, 4, 5]);
+        assert_eq!(annotate_stock(&mut other), Matched::default());
+        assert_eq!(other.rows[0][0].char, None);
+    }
+
+    #[test]
+    fn test_assign_ids() {
+        let mut lengths = text_row_lengths();
+        lengths.extend(BTS_ICON_ROWS.iter().map(|row| row.glyphs.len()));
+        let ids = BTS_IDS.assign(&numbered(&lengths));
+
+        assert_eq!(ids[0][32], GameId::Text('A'));
+        assert_eq!(ids[3][5].id(), Some('™' as u32));
+        let firsts = ids[4..].iter().map(|row| row[0]).collect::<Vec<_>>();
+        assert_eq!(
+            firsts,
+            [8483, 8500, 8550, 8600, 8650].map(GameId::Icon).to_vec()
+        );
+        assert_eq!(ids[7][35], GameId::Icon(8635));
+        assert_eq!(
+            BTS_IDS.expected_row_starts(&BTS_COUNTS),
+            firsts.iter().filter_map(|id| id.id()).collect::<Vec<_>>()
+        );
+
+        /* 24 bonuses and the generic icon, a full row but still short of infos */
+        let mut lengths = text_row_lengths();
+        lengths.extend(BTS_ICON_ROWS.iter().map(|row| row.glyphs.len()));
+        lengths[7] = 25;
+        let ids = BTS_IDS.assign(&numbered(&lengths));
+        let counts = InfoCounts {
+            bonuses: 24,
+            ..BTS_COUNTS
+        };
+        assert_eq!(ids[8][0], GameId::Icon(8675));
+        assert_eq!(BTS_IDS.expected_row_starts(&counts)[4], 8675);
+
+        /* a long text row leaves the rest of the text row unused */
+        let ids = BTS_IDS.assign(&numbered(&[180, 2, 3]));
+        assert_eq!(ids[1], vec![GameId::Text('’'), GameId::Unused]);
+        assert_eq!(ids[2][0], GameId::Icon(8483));
+    }
+}
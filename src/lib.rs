@@ -0,0 +1,352 @@
+//! Packing and unpacking the GameFont.tga atlas from Civilization IV.
+//!
+//! The functions here work on images and manifests in memory, for tools that want to build an
+//! atlas without going through the filesystem or the `atlast` program. The modules are what the
+//! program is built out of and are public too, but change more often.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+use image::codecs::tga::TgaEncoder;
+use image::{ExtendedColorType, ImageFormat, RgbaImage};
+
+pub mod atlas;
+pub mod check;
+pub mod compose;
+pub mod diff;
+pub mod edit;
+pub mod effects;
+pub mod index;
+pub mod layout;
+pub mod manifest;
+pub mod merge;
+pub mod point;
+pub mod preview;
+pub mod render;
+pub mod scale;
+pub mod simulate;
+pub mod xml;
+
+pub use atlas::{Atlas, Glyph};
+pub use image;
+pub use index::{Clipped, Index, IndexGlyph, Layout, LoadedGlyph, LoadedIndex};
+pub use manifest::{IndexFormat, ManifestError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    /// `action` is what was being done to `path`, like "read" or "write".
+    #[error("{action} {}", path.display())]
+    Io {
+        action: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// Like `Io`, but for an image that couldn't be opened or saved.
+    #[error("{action} {}", path.display())]
+    ImageFile {
+        action: &'static str,
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    #[error("no index.toml, index.json, or index.html in {}", dir.display())]
+    NoManifest { dir: PathBuf },
+    /// `path` is just the file name when it's not from a file.
+    #[error("parse {}", path.display())]
+    Manifest {
+        path: PathBuf,
+        format: IndexFormat,
+        source: ManifestError,
+    },
+    #[error("parse {}", path.display())]
+    Xml {
+        path: PathBuf,
+        source: roxmltree::Error,
+    },
+    #[error("no image for {path}")]
+    MissingImage { path: String },
+    #[error("no row {row}, there are {rows}")]
+    NoRow { row: usize, rows: usize },
+    #[error("no glyph {col} in row {row}, it has {len}")]
+    NoGlyph { row: usize, col: usize, len: usize },
+    #[error("{selector}: {name} only has {rows} rows")]
+    SelectorRows {
+        selector: String,
+        name: String,
+        rows: usize,
+    },
+    #[error("{selector}: row {row} only has {len} glyphs")]
+    SelectorGlyphs {
+        selector: String,
+        row: usize,
+        len: usize,
+    },
+    #[error("cowardly refusing to make an image with dimensions {width}x{height}")]
+    Size { width: u32, height: u32 },
+    #[error("{path} in row {row} is {width} wide with its frame, more than the {wrap} to wrap at")]
+    TooWide {
+        path: String,
+        row: usize,
+        width: u32,
+        wrap: u32,
+    },
+    #[error(
+        "{} glyphs don't fit in the atlas{}",
+        .0.len(),
+        .0.first().map(|c| format!(", like {c}")).unwrap_or_default()
+    )]
+    Clipped(Vec<Clipped>),
+    #[error("wrapping rows needs a width to wrap at")]
+    WrapWithoutWidth,
+    #[error("rows can't be wrapped when keeping the layout")]
+    WrapKeptLayout,
+    #[error(
+        "wrapped at {width} wide, the rows need {needed} pixels of height but the atlas is \
+         {height} tall"
+    )]
+    WrappedTooTall {
+        width: u32,
+        height: u32,
+        needed: u32,
+    },
+    #[error(
+        "the atlas would need to be {}x{} to fit a {}x{} glyph, it's {}x{}",
+        .needed.0, .needed.1, .glyph.0, .glyph.1, .atlas.0, .atlas.1
+    )]
+    AtlasTooSmall {
+        needed: (u32, u32),
+        glyph: (u32, u32),
+        atlas: (u32, u32),
+    },
+    #[error("font has no units per em?")]
+    NoUnitsPerEm,
+    #[error("nothing to draw in {text:?}")]
+    NothingToDraw { text: String },
+}
+
+/// How `pack` lays out glyphs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PackOptions {
+    /// The atlas size, or zeroes for just big enough. With `keep_layout`, zeroes mean the size
+    /// in the index, if it has one.
+    pub size: (u32, u32),
+    /// Put glyphs back where they were unpacked from, see `LoadedIndex::kept_layout`.
+    pub keep_layout: bool,
+    /// Break rows wider than the width in `size` onto new rows. Needs a width, and can't be
+    /// used with `keep_layout`.
+    pub wrap: bool,
+    /// Pack glyphs that run off the edge instead of failing with `Error::Clipped`.
+    pub allow_clip: bool,
+}
+
+impl PackOptions {
+    /// Fail for options that can't be packed with, before anything is loaded.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.wrap && self.keep_layout {
+            return Err(Error::WrapKeptLayout);
+        }
+        if self.wrap && self.size.0 == 0 {
+            return Err(Error::WrapWithoutWidth);
+        }
+        Ok(())
+    }
+}
+
+/// An atlas packed by `pack_loaded`.
+#[derive(Debug)]
+pub struct Packed {
+    pub atlas: RgbaImage,
+    /// The glyphs as packed, in rows after wrapping.
+    pub loaded: LoadedIndex,
+    /// Glyphs that run off the edge, only ever some with `allow_clip`.
+    pub clipped: Vec<Clipped>,
+    /// How many rows were wrapped.
+    pub wrapped: usize,
+}
+
+/// Glyphs unpacked by `unpack`.
+#[derive(Debug, Clone, Default)]
+pub struct Unpacked {
+    /// Numbered like `000.png`, with where each glyph was and the size of the atlas.
+    pub index: Index,
+    /// The image of each glyph, by its path in `index`.
+    pub images: BTreeMap<String, RgbaImage>,
+}
+
+/// Decode a .tga.
+pub fn read_tga(bytes: &[u8]) -> Result<RgbaImage, Error> {
+    Ok(image::load_from_memory_with_format(bytes, ImageFormat::Tga)?.into_rgba8())
+}
+
+/// Encode a .tga like the ones `atlast pack` writes, uncompressed like the game's own.
+pub fn write_tga(atlas: &RgbaImage) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    TgaEncoder::new(&mut bytes).disable_rle().encode(
+        atlas.as_raw(),
+        atlas.width(),
+        atlas.height(),
+        ExtendedColorType::Rgba8,
+    )?;
+    Ok(bytes)
+}
+
+/// Parse an index.html, index.json, or index.toml.
+pub fn read_manifest(contents: &str, format: IndexFormat) -> Result<Index, Error> {
+    Index::deserialize(contents, format).map_err(|source| Error::Manifest {
+        path: format.file_name().into(),
+        format,
+        source,
+    })
+}
+
+/// Write an index.html, index.json, or index.toml.
+pub fn write_manifest(index: &Index, format: IndexFormat) -> Result<String, Error> {
+    index.serialize(format).map_err(|source| Error::Manifest {
+        path: format.file_name().into(),
+        format,
+        source,
+    })
+}
+
+/// Find the rows of glyphs in an atlas.
+pub fn detect(atlas: &RgbaImage) -> Atlas<'_> {
+    Atlas::from_image(atlas)
+}
+
+/// Copy each glyph out of an atlas.
+pub fn unpack(atlas: &RgbaImage) -> Unpacked {
+    let detected = detect(atlas);
+    let index = detected.to_index();
+    let images = detected
+        .load_images(index.clone())
+        .rows
+        .into_iter()
+        .flatten()
+        .map(|loaded| (loaded.glyph.path, loaded.image))
+        .collect();
+    Unpacked { index, images }
+}
+
+/// Pack an atlas from `index`, with each glyph's image given by `image` for its path.
+pub fn pack(
+    index: &Index,
+    image: impl FnMut(&str) -> Option<RgbaImage>,
+    options: &PackOptions,
+) -> Result<RgbaImage, Error> {
+    let loaded = index.clone().with_images(image)?;
+    Ok(pack_loaded(loaded, index.size, options)?.atlas)
+}
+
+/// `pack` for glyphs that already have their images. `index_size` is the size the index was
+/// unpacked at, which `keep_layout` uses when `size` is zeroes.
+pub fn pack_loaded(
+    mut loaded: LoadedIndex,
+    index_size: Option<(u32, u32)>,
+    options: &PackOptions,
+) -> Result<Packed, Error> {
+    options.check()?;
+    let (width, height) = options.size;
+    let mut wrapped = 0;
+    if options.wrap {
+        wrapped = loaded.wrap_rows(width)?;
+        let (_, needed) = loaded.packed_size();
+        if wrapped > 0 && height != 0 && needed > height {
+            return Err(Error::WrappedTooTall {
+                width,
+                height,
+                needed,
+            });
+        }
+    }
+
+    let layout = match (options.keep_layout, index_size) {
+        /* an explicit size wins over the size it was unpacked at */
+        (true, Some(recorded)) if options.size == (0, 0) => loaded.kept_layout(recorded)?,
+        (true, _) => loaded.kept_layout(options.size)?,
+        (false, _) => loaded.flow_layout(options.size)?,
+    };
+    let clipped = loaded.clipped(&layout);
+    if !clipped.is_empty() && !options.allow_clip {
+        return Err(Error::Clipped(clipped));
+    }
+    Ok(Packed {
+        atlas: loaded.draw(&layout),
+        loaded,
+        clipped,
+        wrapped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TGA: &[u8] = include_bytes!("../test.tga");
+
+    #[test]
+    fn test_unpack_pack() {
+        let original = read_tga(TGA).unwrap();
+        let unpacked = unpack(&original);
+        let manifest = write_manifest(&unpacked.index, IndexFormat::Toml).unwrap();
+        let index = read_manifest(&manifest, IndexFormat::Toml).unwrap();
+
+        let options = PackOptions {
+            keep_layout: true,
+            ..PackOptions::default()
+        };
+        let packed = pack(&index, |path| unpacked.images.get(path).cloned(), &options).unwrap();
+        assert!(read_tga(&write_tga(&packed).unwrap()).unwrap() == original);
+
+        let small = PackOptions {
+            size: (8, 8),
+            ..PackOptions::default()
+        };
+        let clipped = pack(&index, |path| unpacked.images.get(path).cloned(), &small);
+        assert!(matches!(clipped, Err(Error::Clipped(_))));
+
+        let wrap = |size, keep_layout| {
+            let options = PackOptions {
+                size,
+                keep_layout,
+                wrap: true,
+                ..PackOptions::default()
+            };
+            pack(&index, |path| unpacked.images.get(path).cloned(), &options)
+        };
+        assert!(matches!(wrap((0, 0), false), Err(Error::WrapWithoutWidth)));
+        assert!(matches!(wrap((40, 0), true), Err(Error::WrapKeptLayout)));
+        assert!(matches!(
+            wrap((12, 16), false),
+            Err(Error::WrappedTooTall { width: 12, .. })
+        ));
+        assert!(wrap((12, 0), false).unwrap().width() <= 12);
+        let missing = pack(&index, |_| None, &options);
+        assert!(matches!(missing, Err(Error::MissingImage { .. })));
+    }
+
+    #[test]
+    fn test_errors() {
+        let toml = read_manifest("rows = 7", IndexFormat::Toml);
+        assert!(matches!(
+            toml,
+            Err(Error::Manifest {
+                source: ManifestError::TomlRead(_),
+                ..
+            })
+        ));
+        let html = r#"<div data-atlas="row"><img src="a.png" data-at="7"></div>"#;
+        let Err(Error::Manifest { path, source, .. }) = read_manifest(html, IndexFormat::Html)
+        else {
+            panic!("parsed a bad data-at");
+        };
+        assert_eq!(path, PathBuf::from("index.html"));
+        assert!(matches!(source, ManifestError::Attribute { found, .. } if found == "7"));
+
+        assert_eq!(
+            Error::Clipped(vec![]).to_string(),
+            "0 glyphs don't fit in the atlas"
+        );
+    }
+}
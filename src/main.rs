@@ -1,182 +1,524 @@
-use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::{Path, MAIN_SEPARATOR};
+use std::path::Path;
 use std::process::exit;
 
 use anyhow::{Context, Result};
 
-use crate::atlas::Atlas;
-use crate::index::Index;
+use ab_glyph::FontVec;
+use image::{GenericImageView, ImageFormat, RgbaImage};
 
-pub(crate) mod atlas;
-pub(crate) mod index;
-pub(crate) mod point;
+use atlast::atlas::Atlas;
+use atlast::check::Problem;
+use atlast::effects::Effects;
+use atlast::index::{Index, LoadedIndex};
+use atlast::manifest::IndexFormat;
+use atlast::render::RenderOptions;
+use atlast::scale::Filter;
+use atlast::simulate::SimulateOptions;
+use atlast::xml::XmlIcons;
+use atlast::{check, compose, diff, edit, layout, merge, render, scale, simulate};
+use clap::Parser;
+
+use crate::cli::Command;
+use crate::project::Project;
+use crate::report::{progress, warning, Class, MessageFormat, TimeSince};
+
+pub(crate) mod cli;
+pub(crate) mod project;
+pub(crate) mod report;
+pub(crate) mod serve;
+pub(crate) mod watch;
 
 fn main() {
-    let argv = std::env::args().collect::<Vec<String>>();
-    let mut args = argv.iter().map(String::as_str).peekable();
-    let exe = args
-        .next()
-        .map(|s| s.rsplit(MAIN_SEPARATOR).next().unwrap_or(s))
-        .unwrap_or("atlast");
-
-    #[derive(Debug)]
-    enum Mode<'s> {
-        Pack(&'s str),
-        Unpack(&'s str),
-    }
-
-    let mut mode = Option::<Mode>::None;
-    let mut output = Option::<&str>::None;
-    let mut dry_run = false;
-    let mut index = IndexMode::Overwrite;
-    let mut size = (0, 0);
-
-    while let Some(arg) = args.next() {
-        match arg {
-            "--pack" => {
-                let dir = args
-                    .peek()
-                    .filter(|peek| !peek.starts_with('-'))
-                    .map(drop)
-                    .and_then(|_| args.next())
-                    .unwrap_or("GameFont");
-                mode.replace(Mode::Pack(dir));
-            }
-            "--unpack" => {
-                let tga = args
-                    .peek()
-                    .filter(|peek| !peek.starts_with('-'))
-                    .map(drop)
-                    .and_then(|_| args.next())
-                    .unwrap_or("GameFont.tga");
-                mode.replace(Mode::Unpack(tga));
-            }
-            "--output" => {
-                output.replace(args.next().unwrap_or_else(|| usage_and_exit(exe)));
-            }
-            "-n" | "--dry-run" => dry_run = true,
-            "--skip-index" => index = IndexMode::Skip,
-            "--patch-index" => index = IndexMode::Patch,
-            "--size" => {
-                size = args.next().and_then(parse_dims).unwrap_or_else(|| {
-                    eprintln!("expected --size [WIDTH]x[HEIGHT]");
-                    usage_and_exit(exe);
-                });
-            }
-            "-h" | "--help" => usage_and_exit(exe),
-            _ => {
-                eprintln!("unexpected argument: {arg}");
-                usage_and_exit(exe);
-            }
+    let mut argv = std::env::args().collect::<Vec<String>>();
+    /* commands used to be flags, like `--unpack GameFont.tga` */
+    let mut renamed = None;
+    if let Some(arg) = argv.get_mut(1) {
+        if let Some(command) = arg
+            .strip_prefix("--")
+            .filter(|command| cli::COMMANDS.contains(command))
+        {
+            *arg = command.to_owned();
+            renamed = Some(arg.clone());
         }
     }
-
-    let Some(mode) = mode else {
-        usage_and_exit(exe)
+    let cli = match cli::Cli::try_parse_from(&argv) {
+        Ok(cli) => cli,
+        /* --help and --version, and errors when they aren't wanted as JSON */
+        Err(err) if !err.use_stderr() || !cli::wants_json(&argv) => err.exit(),
+        Err(err) => {
+            report::set_format(MessageFormat::Json);
+            let rendered = err.render().to_string();
+            let message = rendered.lines().next().unwrap_or_default();
+            let message = message.strip_prefix("error: ").unwrap_or(message);
+            exit(report::failure(&report::failed(Class::Args, message)));
+        }
     };
+    report::set_format(cli.message_format);
+    if let Some(command) = renamed {
+        warning!("--{command} is now just `{command}`");
+    }
 
-    let output = output.map(Cow::from).unwrap_or_else(|| {
-        /* infer output filename */
-        match mode {
-            Mode::Pack(dir) => Path::new(dir)
-                .canonicalize()
-                .ok()
-                .and_then(|path| {
-                    path.file_name()
-                        .and_then(|osstr| osstr.to_str())
-                        .map(|name| format!("{name}.tga").into())
-                })
-                .unwrap_or("GameFont.tga".into()),
-            Mode::Unpack(tga) => Path::new(tga)
-                .file_stem()
-                .and_then(|osstr| osstr.to_str())
-                .unwrap_or("GameFont")
-                .into(),
+    if let Err(err) = run(cli.command) {
+        exit(report::failure(&err));
+    }
+}
+
+/// Do what `command` asks, with paths relative to the working directory.
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Unpack(args) => {
+            let output = args.output.output.unwrap_or_else(|| {
+                Path::new(&args.tga)
+                    .file_stem()
+                    .and_then(|osstr| osstr.to_str())
+                    .unwrap_or("GameFont")
+                    .to_owned()
+            });
+            let index = match (args.skip_index, args.patch_index) {
+                (true, _) => IndexMode::Skip,
+                (_, true) => IndexMode::Patch,
+                _ => IndexMode::Overwrite,
+            };
+            unpack_to_dir(
+                &output,
+                &args.tga,
+                args.output.dry_run,
+                index,
+                args.index_format,
+                !args.numbered,
+            )
         }
-    });
+        Command::Pack(args) => pack(&args, None),
+        Command::PackPair(args) => pack(&args.pack, Some(&args)),
+        Command::Scale(args) => {
+            let output = args.output.output.unwrap_or_else(|| {
+                format!("{}_{}", dir_name(&args.dir), (args.factor * 100.).round())
+            });
+            scale_to_dir(
+                &output,
+                &args.dir,
+                args.output.dry_run,
+                args.factor,
+                args.filter,
+            )
+        }
+        Command::RenderText(args) => {
+            let output = args.output.output.as_deref().unwrap_or("GameFont.tga");
+            let options = RenderOptions {
+                size: args.size,
+                color: args.color,
+                ..RenderOptions::default()
+            };
+            render_text(
+                output,
+                &args.font,
+                args.text.as_deref(),
+                args.output.dry_run,
+                &options,
+                &Effects::from(&args.effects),
+                args.index_format,
+            )
+        }
+        Command::Check(args) => check(&args.input),
+        Command::Ids(args) => print_ids(&args.input, args.previous.as_deref(), !args.numbered),
+        Command::Diff(args) => diff(
+            &args.old,
+            &args.new,
+            args.report.as_deref(),
+            args.dry_run,
+            !args.numbered,
+        ),
+        Command::Merge(args) => {
+            let output = args
+                .output
+                .output
+                .unwrap_or_else(|| format!("{}_merged", dir_name(&args.ours)));
+            merge_to_dir(
+                &output,
+                [&args.base, &args.ours, &args.theirs],
+                args.output.dry_run,
+                args.index_format,
+                !args.numbered,
+            )
+        }
+        Command::Compose(args) => {
+            let output = args.output.output.as_deref().unwrap_or("GameFont_composed");
+            let selectors = args
+                .selectors
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>();
+            compose_to_dir(
+                output,
+                &selectors,
+                args.output.dry_run,
+                args.index_format,
+                !args.numbered,
+                args.check_xml.as_deref(),
+            )
+        }
+        Command::Extract(args) => {
+            /* empty to name the image like unpack would */
+            let output = args.output.output.as_deref().unwrap_or("");
+            extract_glyph(
+                output,
+                &args.tga,
+                args.glyph,
+                args.output.dry_run,
+                !args.numbered,
+            )
+        }
+        Command::Serve(args) => serve(&args),
+        Command::Simulate(args) => simulate(&args),
+        Command::Replace(args) => {
+            let output = args.output.output.as_deref().unwrap_or(&args.tga);
+            replace_glyph(
+                output,
+                &args.tga,
+                &args.png,
+                args.glyph,
+                args.output.dry_run,
+            )
+        }
+    }
+}
+
+/// The name of a directory, or the directory a manifest file is in, like `GameFont`.
+fn dir_name(path: &str) -> String {
+    Path::new(path)
+        .canonicalize()
+        .ok()
+        .and_then(|path| {
+            let dir = if path.is_file() {
+                path.parent()?
+            } else {
+                &path
+            };
+            dir.file_name()
+                .and_then(|osstr| osstr.to_str())
+                .map(str::to_owned)
+        })
+        .unwrap_or("GameFont".into())
+}
+
+/// `pack`, or `pack-pair` if given its arguments too, with defaults from the directory's
+/// atlast.toml.
+fn pack(args: &cli::PackArgs, pair: Option<&cli::PackPairArgs>) -> Result<()> {
+    let project = read_project(&args.dir)?;
 
-    let res = match mode {
-        Mode::Pack(dir) => pack_to_tga(&output, dir, dry_run, size),
-        Mode::Unpack(tga) => unpack_to_dir(&output, tga, dry_run, index),
+    let output = (args.output.output.clone())
+        .or(project.output)
+        .unwrap_or_else(|| format!("{}.tga", dir_name(&args.dir)));
+    let check_xml = args.check_xml.as_deref().or(project.check_xml.as_deref());
+    let options = Packing {
+        pack: atlast::PackOptions {
+            size: args.size.or(project.size).unwrap_or((0, 0)),
+            keep_layout: args.keep_layout().unwrap_or(project.keep_layout),
+            wrap: args.wrap().unwrap_or(project.wrap),
+            allow_clip: args.allow_clip().unwrap_or(project.allow_clip),
+        },
+        also_75: pair.map(|pair| {
+            (
+                pair.size_75.or(project.size_75).unwrap_or((0, 0)),
+                pair.filter.or(project.filter).unwrap_or_default(),
+            )
+        }),
+        effects: &Effects::from(&args.effects),
+        check_xml,
     };
+    match args.watch {
+        true => watch::pack_on_change(&output, &args.dir, args.output.dry_run, &options),
+        false => pack_to_tga(&output, &args.dir, args.output.dry_run, &options),
+    }
+}
+
+/// Preview `pack` with the defaults from the directory's atlast.toml.
+fn serve(args: &cli::ServeArgs) -> Result<()> {
+    let project = read_project(&args.dir)?;
+    let options = Packing {
+        pack: atlast::PackOptions {
+            size: project.size.unwrap_or((0, 0)),
+            keep_layout: project.keep_layout,
+            wrap: project.wrap,
+            /* show what doesn't fit */
+            allow_clip: true,
+        },
+        also_75: None,
+        effects: &Effects::default(),
+        check_xml: None,
+    };
+    serve::serve(&args.dir, args.port, &options)
+}
 
-    if let Err(err) = res {
-        eprintln!("oopsie woopsie!");
-        eprintln!("{err:#}");
+/// The directory's atlast.toml, or all defaults if there isn't one.
+fn read_project(dir: &str) -> Result<Project> {
+    let project = Project::read_from_dir(dir)?;
+    if project.is_some() {
+        report::emit_progress(
+            None,
+            format_args!(
+                "using defaults from {}",
+                Path::new(dir).join(Project::FILE_NAME).display()
+            ),
+        );
     }
+    Ok(project.unwrap_or_default())
 }
 
-fn usage_and_exit(exe: &str) -> ! {
-    eprintln!(
-        r#"usage: {exe} [options]
-  options can include:
-  --unpack [GameFont.tga]    unpack GameFont.tga to a directory
-  --pack [GameFont/]         opposite of unpack, write GameFont.tga using unpacked files
-  -n, --dry-run              read but don't write files
-  -n, --dry-run              read but don't write files
-  --output ...               when used with --unpack, sets the output directory
-                             when used with --pack, sets the output .tga file
-  --skip-index               with --unpack, do not write index.html
-  --patch-index              with --unpack, only update matching images in index.html
-  --size [WIDTH]x[HEIGHT]    with --pack, sets .tga file dimensions
-
-examples:
-
-  {exe} --unpack
-    Read `GameFont.tga` and write each glyph as a .png file in the
-    `GameFont` directory with an `index.html` needed for repacking.
-
-  {exe} --unpack GameFont_75.tga
-    Unpack `GameFont_75.tga` to the `GameFont_75` directory.
-
-  {exe} --unpack SpecialGameFont.tga --output GameFont_75 --patch-index
-    Unpack `SpecialGameFont.tga` to the `GameFont_75` directory. Instead of overwriting
-    `GameFont_75/index.html`, only update `<img>` elements with paths that match the image files
-    unpacked from the .tga file. This could be useful if you're unpacking a .tga that contains just
-    the text portion of the atlas and want to update just the descent/baseline markers for those
-    images in the html file.
-
-  {exe} --pack --output SexyLettuce.tga
-    Read the `index.html` in the `GameFont` directory and pack the
-    images listed there into an atlas named `SexyLettuce.tga`.
-
-The index.html is used as a manifest for repacking GameFont.tga and contains information about
-descent/baseline markers.
-"#
-    );
-    exit(2);
+/// How `pack` and `pack-pair` pack, besides the layout.
+struct Packing<'a> {
+    pack: atlast::PackOptions,
+    /// also pack a 75% atlas of this size, resampled with this filter
+    also_75: Option<((u32, u32), Filter)>,
+    effects: &'a Effects,
+    /// check against the XML in this Assets directory first
+    check_xml: Option<&'a str>,
 }
 
-fn pack_to_tga(destination: &str, input: &str, dry_run: bool, size: (u32, u32)) -> Result<()> {
+fn pack_to_tga(destination: &str, input: &str, dry_run: bool, options: &Packing) -> Result<()> {
     let ts = TimeSince::default();
+    options.pack.check()?;
 
-    eprintln!("{ts} packing images under {input} to {destination}");
-    let index_path = Path::new(input).join("index.html");
-    let index_contents = fs::read_to_string(&index_path)
-        .with_context(|| format!("read {}", index_path.display()))?;
-    let index = Index::from_html(&index_contents)
-        .with_context(|| format!("parse {}", index_path.display()))?;
+    progress!(ts, "packing images under {input} to {destination}");
+    let (format, index) = read_index_dir(input)?;
+    progress!(ts, "read {}", format.path_in(input).display());
 
-    eprintln!("{ts} loading {} images...", index.len());
+    if let Some(assets) = options.check_xml {
+        check_xml(&ts, input, &index, assets)?;
+    }
+
+    progress!(ts, "loading {} images...", index.len());
+    let index_size = index.size;
     let loaded_index = index.load_images(input)?;
+    let atlases = pack_loaded(&ts, destination, loaded_index, index_size, options)?;
+
+    if dry_run {
+        progress!(ts, "dry run, not writing {destination}");
+        return Ok(());
+    }
+
+    for (destination, atlas) in atlases {
+        save_atomically(&atlas, &destination)?;
+        progress!(ts, "written to {destination}");
+    }
+
+    Ok(())
+}
+
+/// Fail if `index` doesn't line up with the XML in `assets`.
+fn check_xml(ts: &TimeSince, input: &str, index: &Index, assets: &str) -> Result<()> {
+    let icons = XmlIcons::scan(assets)?;
+    progress!(
+        ts,
+        "found {} FontButtonIndex references under {assets}",
+        icons.refs.len()
+    );
+    let problems = icons.check(index);
+    problems.iter().for_each(report::problem);
+    let errors = check::count_errors(&problems);
+    if errors > 0 {
+        return Err(report::failed(
+            Class::Invalid,
+            format!("{input} doesn't match the XML in {assets}, {errors} errors"),
+        ));
+    }
+    Ok(())
+}
+
+/// Apply effects and pack with `atlast::pack_loaded`, returning each atlas to write and where,
+/// the 75% one second. Fails if glyphs don't fit in either, unless `allow_clip`.
+fn pack_loaded(
+    ts: &TimeSince,
+    destination: &str,
+    mut loaded_index: LoadedIndex,
+    index_size: Option<(u32, u32)>,
+    options: &Packing,
+) -> Result<Vec<(String, RgbaImage)>> {
+    let Packing {
+        pack,
+        also_75,
+        effects,
+        check_xml: _,
+    } = *options;
+
+    if !effects.is_noop() {
+        progress!(ts, "applying outline and shadow...");
+        effects.apply_to_index(&mut loaded_index);
+    }
+
+    let before = pack
+        .wrap
+        .then(|| layout::BTS_IDS.assign(&loaded_index.to_index()));
+    /* glyphs that don't fit are reported below, along with the 75% atlas's */
+    let packed = atlast::pack_loaded(
+        loaded_index,
+        index_size,
+        &atlast::PackOptions {
+            allow_clip: true,
+            ..pack
+        },
+    )?;
+    if let Some(before) = before.filter(|_| packed.wrapped > 0) {
+        progress!(
+            ts,
+            "wrapped {} rows at {} wide",
+            packed.wrapped,
+            pack.size.0
+        );
+        warn_moved_ids(&before, &packed.loaded);
+    }
+    let (atlas, loaded_index) = (packed.atlas, packed.loaded);
+    let mut clipped = vec![(destination.to_owned(), packed.clipped)];
+    progress!(ts, "packed {}x{}", atlas.width(), atlas.height());
+
+    /* Both are packed before either is written so a failure doesn't leave them out of sync. */
+    let atlas_75 = match also_75 {
+        Some(((width, height), filter)) => {
+            let scaled = scale::scale_index(&loaded_index, scale::SCALE_75, filter);
+            /* frame pixels aren't scaled, so 75% of the full size might not fit */
+            let (scaled_width, scaled_height) =
+                scale::scale_size(atlas.dimensions(), scale::SCALE_75);
+            let (packed_width, packed_height) = scaled.packed_size();
+            let pick = |fixed: u32, scaled: u32, packed: u32| match fixed {
+                0 => scaled.max(packed),
+                _ => fixed,
+            };
+            let size = (
+                pick(width, scaled_width, packed_width),
+                pick(height, scaled_height, packed_height),
+            );
+            let layout = scaled.flow_layout(size)?;
+            clipped.push((
+                path_with_suffix(destination, "_75"),
+                scaled.clipped(&layout),
+            ));
+            let atlas_75 = scaled.draw(&layout);
+            progress!(
+                ts,
+                "packed {}x{} at 75%",
+                atlas_75.width(),
+                atlas_75.height()
+            );
+            Some((path_with_suffix(destination, "_75"), atlas_75))
+        }
+        None => None,
+    };
+    let atlases = std::iter::once((destination.to_owned(), atlas))
+        .chain(atlas_75)
+        .collect();
+
+    let mut clipped_count = 0;
+    for (destination, clipped) in &clipped {
+        for glyph in clipped {
+            let message = format!("{glyph} runs off the edge of {destination}");
+            report::problem(&match pack.allow_clip {
+                true => Problem::warning(message),
+                false => Problem::error(message),
+            });
+        }
+        clipped_count += clipped.len();
+    }
+    if clipped_count > 0 && !pack.allow_clip {
+        return Err(report::failed(
+            Class::Layout,
+            format!("{clipped_count} glyphs don't fit, use a bigger --size, --wrap, or --allow-clip to write it anyway"),
+        ));
+    }
+
+    Ok(atlases)
+}
+
+/// Write to a file next to `destination` and rename it over, so the game or anything watching
+/// never reads half an atlas.
+fn save_atomically(atlas: &RgbaImage, destination: &str) -> Result<()> {
+    let format =
+        ImageFormat::from_path(destination).with_context(|| format!("save {destination}"))?;
+    let temp = format!("{destination}.tmp");
+    let saved = match format {
+        /* the encoder's default is RLE, which the game's own atlases aren't */
+        ImageFormat::Tga => atlast::write_tga(atlas)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(fs::write(&temp, bytes)?)),
+        format => Ok(atlas.save_with_format(&temp, format)?),
+    }
+    .and_then(|()| Ok(fs::rename(&temp, destination)?));
+    if saved.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    saved.with_context(|| format!("save {destination}"))
+}
+
+/// Warn about glyphs whose game ID changed from `before` by wrapping rows.
+fn warn_moved_ids(before: &[Vec<layout::GameId>], loaded: &LoadedIndex) {
+    let after = layout::BTS_IDS.assign(&loaded.to_index());
+    let moved = loaded
+        .rows
+        .iter()
+        .flatten()
+        .zip(before.iter().flatten().zip(after.iter().flatten()))
+        .filter(|(_, (before, after))| before != after)
+        .map(|(loaded, (before, after))| (&loaded.glyph.path, before, after))
+        .collect::<Vec<_>>();
+    if let Some((path, before, after)) = moved.first() {
+        warning!(
+            "wrapping changed the game ID of {} glyphs, starting with {path} from {before} to {after}",
+            moved.len()
+        );
+    }
+}
+
+fn scale_to_dir(
+    destination: &str,
+    input: &str,
+    dry_run: bool,
+    factor: f32,
+    filter: Filter,
+) -> Result<()> {
+    let ts = TimeSince::default();
 
-    let atlas = loaded_index.to_atlas_image(size)?;
-    eprintln!("{ts} packed {}x{}", atlas.width(), atlas.height());
+    progress!(
+        ts,
+        "scaling images under {input} by {factor} to {destination}"
+    );
+    let (format, index) = read_index_dir(input)?;
+    progress!(ts, "read {}", format.path_in(input).display());
+
+    progress!(ts, "loading {} images...", index.len());
+    let loaded = index.load_images(input)?;
+    let scaled = scale::scale_index(&loaded, factor, filter);
+    progress!(ts, "scaled {} images with {filter:?}", scaled.len());
 
     if dry_run {
-        eprintln!("{ts} dry run, not writing {destination}");
+        progress!(ts, "dry run, not writing {destination}");
         return Ok(());
     }
 
-    atlas
-        .save(destination)
-        .with_context(|| format!("save {destination}"))?;
-    eprintln!("{ts} written to {destination}");
+    fs::create_dir_all(destination).context("open destination")?;
+    scaled
+        .save_images(destination)
+        .context("save glyph images")?;
+    write_index(&ts, destination, &scaled.to_index(), format)?;
+    progress!(ts, "done scaling to {destination}");
 
     Ok(())
 }
 
+/// `GameFont.tga` with `_75` is `GameFont_75.tga`.
+fn path_with_suffix(path: &str, suffix: &str) -> String {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("GameFont");
+    let name = match path.extension().and_then(|s| s.to_str()) {
+        Some(ext) => format!("{stem}{suffix}.{ext}"),
+        None => format!("{stem}{suffix}"),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
 #[derive(Debug)]
 enum IndexMode {
     Skip,
@@ -189,96 +531,642 @@ fn unpack_to_dir(
     input: &str,
     dry_run: bool,
     index_mode: IndexMode,
+    index_format: IndexFormat,
+    stock_names: bool,
 ) -> Result<()> {
     let ts = TimeSince::default();
 
-    eprintln!("{ts} loading {input} to unpack to {destination}... ");
+    progress!(ts, "loading {input} to unpack to {destination}... ");
     let img = image::open(input).with_context(|| format!("open {input}"))?;
     let buf = img.into_rgba8();
     let atlas = Atlas::from_image(&buf);
-    eprintln!(
-        "{ts} found {} images over {} rows",
+    progress!(
+        ts,
+        "found {} images over {} rows",
         atlas.len(),
         atlas.row_count()
     );
 
     if dry_run {
-        eprintln!("{ts} dry run, not saving images to {destination}");
+        progress!(ts, "dry run, not saving images to {destination}");
         return Ok(());
     }
 
-    eprintln!("{ts} saving to {destination}...");
+    progress!(ts, "saving to {destination}...");
     fs::create_dir_all(destination).context("open destination")?;
 
-    let index = atlas
-        .save_images(destination)
-        .context("save atlas images")?;
+    let mut index = atlas.to_index();
 
-    let index_path = Path::new(destination).join("index.html");
+    if stock_names {
+        let matched = layout::annotate_stock(&mut index);
+        match (matched.text, matched.icons) {
+            (true, true) => progress!(ts, "looks like the stock layout, naming glyphs"),
+            (true, false) => progress!(ts, "text rows look like the stock layout"),
+            _ => (),
+        }
+    }
+
+    atlas
+        .save_images(destination, &index)
+        .context("save atlas images")?;
 
     match index_mode {
         IndexMode::Skip => (),
-        IndexMode::Overwrite => {
-            eprintln!("{ts} writing {}", index_path.display());
-            fs::write(&index_path, index.to_html())
-                .with_context(|| format!("write {}", index_path.display()))?;
+        IndexMode::Overwrite => write_index(&ts, destination, &index, index_format)?,
+        IndexMode::Patch => match IndexFormat::detect(destination) {
+            None | Some(IndexFormat::Html) => {
+                let index_path = IndexFormat::Html.path_in(destination);
+                progress!(ts, "patching {}", index_path.display());
+                let html = fs::read_to_string(&index_path)
+                    .with_context(|| format!("read {}", index_path.display()))?;
+                let (matched, new_html) = index
+                    .patch_html(&html)
+                    .with_context(|| format!("patch {}", index_path.display()))?;
+                fs::write(&index_path, new_html)
+                    .with_context(|| format!("write {}", index_path.display()))?;
+                progress!(ts, "matched {matched} <img>s");
+            }
+            Some(format) => {
+                let (_, mut existing) = Index::read_from_dir(destination)?;
+                progress!(ts, "patching {}", format.path_in(destination).display());
+                let matched = existing.patch(&index);
+                existing.write_to_dir(destination, format)?;
+                progress!(ts, "matched {matched} glyphs");
+            }
+        },
+    }
+
+    progress!(ts, "done unpacking to {}", destination);
+    Ok(())
+}
+
+fn render_text(
+    destination: &str,
+    font_path: &str,
+    text_path: Option<&str>,
+    dry_run: bool,
+    options: &RenderOptions,
+    effects: &Effects,
+    index_format: IndexFormat,
+) -> Result<()> {
+    let ts = TimeSince::default();
+
+    progress!(
+        ts,
+        "rendering {font_path} at {}px to {destination}...",
+        options.size
+    );
+    let font_data = fs::read(font_path).with_context(|| format!("read {font_path}"))?;
+    let font = FontVec::try_from_vec(font_data).with_context(|| format!("parse {font_path}"))?;
+
+    let text = match text_path {
+        Some(path) => fs::read_to_string(path).with_context(|| format!("read {path}"))?,
+        None => render::DEFAULT_TEXT.to_owned(),
+    };
+
+    let missing = render::missing_chars(&font, &text);
+    if !missing.is_empty() {
+        progress!(
+            ts,
+            "font has no glyphs for: {}",
+            missing.into_iter().collect::<String>()
+        );
+    }
+
+    let mut loaded = render::render_text(&font, &text, options)?;
+    effects.apply_to_index(&mut loaded);
+    progress!(
+        ts,
+        "rendered {} images over {} rows",
+        loaded.len(),
+        loaded.rows.len()
+    );
+
+    if dry_run {
+        progress!(ts, "dry run, not writing {destination}");
+        return Ok(());
+    }
+
+    if is_tga_path(destination) {
+        let atlas = loaded.to_atlas_image((0, 0))?;
+        save_atomically(&atlas, destination)?;
+        progress!(
+            ts,
+            "written {}x{} to {destination}",
+            atlas.width(),
+            atlas.height()
+        );
+    } else {
+        fs::create_dir_all(destination).context("open destination")?;
+        loaded
+            .save_images(destination)
+            .context("save glyph images")?;
+
+        write_index(&ts, destination, &loaded.to_index(), index_format)?;
+        progress!(ts, "done rendering to {destination}");
+    }
+
+    Ok(())
+}
+
+fn check(input: &str) -> Result<()> {
+    let ts = TimeSince::default();
+
+    let (buf, mut problems) = if is_tga_path(input) {
+        let buf = image::open(input)
+            .with_context(|| format!("open {input}"))?
+            .into_rgba8();
+        (buf, vec![])
+    } else {
+        let (format, index) = read_index_dir(input)?;
+        progress!(ts, "read {}", format.path_in(input).display());
+        let loaded = index.load_images(input)?;
+        (loaded.to_atlas_image((0, 0))?, check::check_loaded(&loaded))
+    };
+
+    let atlas = Atlas::from_image(&buf);
+    progress!(
+        ts,
+        "found {} images over {} rows in {input}",
+        atlas.len(),
+        atlas.row_count()
+    );
+    problems.extend(check::check_atlas(&buf, &atlas));
+    problems.iter().for_each(report::problem);
+
+    let errors = check::count_errors(&problems);
+    progress!(ts, "{errors} errors, {} warnings", problems.len() - errors);
+    if errors > 0 {
+        return Err(report::failed(
+            Class::Invalid,
+            format!("{input} has errors"),
+        ));
+    }
+
+    Ok(())
+}
+
+fn print_ids(input: &str, previous: Option<&str>, stock_names: bool) -> Result<()> {
+    let ts = TimeSince::default();
+
+    let index = read_index(input, stock_names)?;
+    let ids = layout::BTS_IDS.assign(&index);
+    progress!(ts, "read {} glyphs from {input}", index.len());
+
+    let previous_ids = match previous {
+        Some(previous) => {
+            let old = read_index(previous, stock_names)?;
+            let old_ids = layout::BTS_IDS.assign(&old);
+            progress!(ts, "read {} glyphs from {previous}", old.len());
+            let ids = old
+                .rows
+                .iter()
+                .flatten()
+                .zip(old_ids.iter().flatten())
+                .map(|(glyph, &id)| (glyph.key(), id))
+                .collect::<BTreeMap<_, _>>();
+            Some(ids)
+        }
+        None => None,
+    };
+
+    let mut seen = BTreeMap::<u32, usize>::new();
+    ids.iter()
+        .flatten()
+        .filter_map(|id| id.id())
+        .for_each(|id| {
+            *seen.entry(id).or_default() += 1;
+        });
+
+    let mut changed = 0;
+    for (r, (row, row_ids)) in index.rows.iter().zip(&ids).enumerate() {
+        for (c, (glyph, &id)) in row.iter().zip(row_ids).enumerate() {
+            let mut flags = Vec::new();
+            if let Some(previous_ids) = &previous_ids {
+                match previous_ids.get(&glyph.key()) {
+                    Some(&old) if old != id => flags.push(format!("was {old}")),
+                    Some(_) => (),
+                    None => flags.push("new".to_owned()),
+                }
+            }
+            if id.id().is_some_and(|id| seen[&id] > 1) {
+                flags.push("duplicate".to_owned());
+            }
+            if !flags.is_empty() {
+                changed += 1;
+            }
+
+            let label = glyph.title().unwrap_or_else(|| glyph.path.clone());
+            match flags.is_empty() {
+                true => println!("{id:>6}  {r:>2},{c:<3} {label}"),
+                false => println!("{id:>6}  {r:>2},{c:<3} {label}  ({})", flags.join(", ")),
+            }
         }
-        IndexMode::Patch => {
-            eprintln!("{ts} patching {}", index_path.display());
-            let html = fs::read_to_string(&index_path)
-                .with_context(|| format!("read {}", index_path.display()))?;
-            let (matched, new_html) = index
-                .patch_html(&html)
-                .with_context(|| format!("patch {}", index_path.display()))?;
-            fs::write(&index_path, new_html)
-                .with_context(|| format!("write {}", index_path.display()))?;
-            eprintln!("{ts} matched {matched} <img>s");
+    }
+
+    if previous.is_some() || changed > 0 {
+        progress!(ts, "{changed} glyphs flagged");
+    }
+
+    Ok(())
+}
+
+fn diff(
+    old: &str,
+    new: &str,
+    report: Option<&str>,
+    dry_run: bool,
+    stock_names: bool,
+) -> Result<()> {
+    let ts = TimeSince::default();
+
+    let old_side = read_side(old, stock_names)?;
+    progress!(ts, "read {} glyphs from {old}", old_side.loaded.len());
+    let new_side = read_side(new, stock_names)?;
+    progress!(ts, "read {} glyphs from {new}", new_side.loaded.len());
+
+    let diffs = diff::diff(&old_side, &new_side);
+    let changed = diffs.iter().filter(|diff| !diff.is_unchanged()).count();
+    diffs
+        .iter()
+        .filter(|diff| !diff.is_unchanged())
+        .for_each(|diff| println!("{diff}"));
+    progress!(ts, "{changed} of {} glyphs differ", diffs.len());
+
+    if let Some(report) = report {
+        let html = diff::html_report(&old_side, &new_side, &diffs)?;
+        if !dry_run {
+            progress!(ts, "writing {report}");
+            fs::write(report, html).with_context(|| format!("write {report}"))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn merge_to_dir(
+    destination: &str,
+    [base, ours, theirs]: [&str; 3],
+    dry_run: bool,
+    index_format: IndexFormat,
+    stock_names: bool,
+) -> Result<()> {
+    let ts = TimeSince::default();
+
+    let [base_side, our_side, their_side] = [base, ours, theirs].map(|input| {
+        let side = read_side(input, stock_names)?;
+        progress!(ts, "read {} glyphs from {input}", side.loaded.len());
+        Ok::<_, anyhow::Error>(side)
+    });
+    let merged = merge::merge(&base_side?, &our_side?, &their_side?);
+    progress!(
+        ts,
+        "merged {} glyphs over {} rows",
+        merged.loaded.len(),
+        merged.loaded.rows.len()
+    );
+    for (slot, conflict) in &merged.conflicts {
+        let loaded = &merged.loaded.rows[slot.row][slot.col];
+        let label = loaded
+            .glyph
+            .title()
+            .unwrap_or_else(|| loaded.glyph.path.clone());
+        report::problem(&Problem::error(format!(
+            "conflict: {slot} {label}: {conflict}"
+        )));
+    }
+
+    if dry_run {
+        progress!(ts, "dry run, not saving to {destination}");
+    } else {
+        progress!(ts, "saving to {destination}...");
+        fs::create_dir_all(destination).context("open destination")?;
+        merged.loaded.save_images(destination)?;
+        (LoadedIndex {
+            rows: vec![merged.extra],
+        })
+        .save_images(destination)?;
+        write_index(&ts, destination, &merged.loaded.to_index(), index_format)?;
+    }
+
+    if !merged.conflicts.is_empty() {
+        return Err(report::failed(
+            Class::Invalid,
+            format!(
+                "{} conflicts, marked with data-{} in {}",
+                merged.conflicts.len(),
+                merge::CONFLICT,
+                index_format.path_in(destination).display()
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Save one glyph from a .tga, named like `unpack` would unless `destination` is given.
+fn extract_glyph(
+    destination: &str,
+    input: &str,
+    (row, col): (usize, usize),
+    dry_run: bool,
+    stock_names: bool,
+) -> Result<()> {
+    let ts = TimeSince::default();
+
+    let buf = image::open(input)
+        .with_context(|| format!("open {input}"))?
+        .into_rgba8();
+    let atlas = Atlas::from_image(&buf);
+    let glyph = edit::find_glyph(&atlas, (row, col))?;
+    let mut index = atlas.to_index();
+    if stock_names {
+        layout::annotate_stock(&mut index);
+    }
+    let found = &index.rows[row][col];
+    progress!(
+        ts,
+        "{} at {},{} is {}x{} with descent {}",
+        found
+            .title()
+            .unwrap_or_else(|| format!("row {row} glyph {col}")),
+        glyph.x(),
+        glyph.y(),
+        glyph.w(),
+        glyph.h(),
+        glyph.descent
+    );
+
+    let destination = match destination {
+        "" => found.path.as_str(),
+        destination => destination,
+    };
+    if dry_run {
+        progress!(ts, "dry run, not writing {destination}");
+        return Ok(());
+    }
+    buf.view(glyph.x(), glyph.y(), glyph.w(), glyph.h())
+        .to_image()
+        .save(destination)
+        .with_context(|| format!("save {destination}"))?;
+    progress!(ts, "written to {destination}");
+
+    Ok(())
+}
+
+fn simulate(args: &cli::SimulateArgs) -> Result<()> {
+    let ts = TimeSince::default();
+
+    let loaded = read_side(&args.input, false)?.loaded;
+    progress!(ts, "read {} glyphs from {}", loaded.len(), args.input);
+    let counts = match &args.xml {
+        Some(assets) => {
+            let icons = XmlIcons::scan(assets)?;
+            icons.malformed.iter().for_each(report::problem);
+            let counts = icons.counts();
+            progress!(
+                ts,
+                "numbering icons for {} religions, {} corporations, and {} bonuses from {assets}",
+                counts.religions,
+                counts.corporations,
+                counts.bonuses
+            );
+            counts
         }
+        None => layout::BTS_COUNTS,
+    };
+    let options = SimulateOptions {
+        counts,
+        space: args.space,
+        background: args.background,
+        guide: args.guide,
+    };
+
+    let simulated = simulate::simulate(&loaded, &args.text, &options)
+        .map_err(|err| report::failed(Class::Args, format!("{err:#}")))?;
+    for missing in &simulated.missing {
+        warning!("no glyph for {missing}, left out");
     }
+    let (width, height) = simulated.image.dimensions();
+    progress!(ts, "drew {} lines in {width}x{height}", simulated.lines);
+
+    let destination = args.output.output.as_deref().unwrap_or("simulated.png");
+    if args.output.dry_run {
+        progress!(ts, "dry run, not writing {destination}");
+        return Ok(());
+    }
+    simulated
+        .image
+        .save(destination)
+        .with_context(|| format!("save {destination}"))?;
+    progress!(ts, "written to {destination}");
 
-    eprintln!("{ts} done unpacking to {}", destination);
     Ok(())
 }
 
-fn parse_dims(s: &str) -> Option<(u32, u32)> {
-    let (w, h) = s.split_once('x')?;
-    Some((parse_dim(w)?, parse_dim(h)?))
+/// Draw a .png over one glyph of a .tga, keeping its baseline marker.
+fn replace_glyph(
+    destination: &str,
+    input: &str,
+    png: &str,
+    at: (usize, usize),
+    dry_run: bool,
+) -> Result<()> {
+    let ts = TimeSince::default();
+
+    let mut buf = image::open(input)
+        .with_context(|| format!("open {input}"))?
+        .into_rgba8();
+    let image = image::open(png)
+        .with_context(|| format!("open {png}"))?
+        .into_rgba8();
+
+    match edit::replace_glyph(&mut buf, at, &image, None)? {
+        edit::Replaced::InPlace => progress!(ts, "replaced row {} glyph {}", at.0, at.1),
+        edit::Replaced::Reflowed => progress!(
+            ts,
+            "replaced row {} glyph {} and moved the glyphs after it over",
+            at.0,
+            at.1
+        ),
+        edit::Replaced::Repacked => progress!(
+            ts,
+            "replaced row {} glyph {}, it didn't fit in its row so the atlas was packed again",
+            at.0,
+            at.1
+        ),
+    }
+
+    if dry_run {
+        progress!(ts, "dry run, not writing {destination}");
+        return Ok(());
+    }
+    save_atomically(&buf, destination)?;
+    progress!(ts, "written to {destination}");
+
+    Ok(())
 }
 
-fn parse_dim(s: &str) -> Option<u32> {
-    match s {
-        "" => Some(0),
-        _ => s.parse().ok(),
+fn compose_to_dir(
+    destination: &str,
+    selectors: &[&str],
+    dry_run: bool,
+    index_format: IndexFormat,
+    stock_names: bool,
+    check_xml: Option<&str>,
+) -> Result<()> {
+    let ts = TimeSince::default();
+
+    let selectors = selectors
+        .iter()
+        .map(|s| {
+            compose::Selector::parse(s).ok_or_else(|| {
+                report::failed(
+                    Class::Args,
+                    format!("expected [+]SOURCE[@ROWS[:GLYPHS]], found: {s}"),
+                )
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut sources = BTreeMap::new();
+    for selector in &selectors {
+        if !sources.contains_key(selector.source) {
+            let side = read_side(selector.source, stock_names)?;
+            progress!(
+                ts,
+                "read {} glyphs over {} rows from {}",
+                side.loaded.len(),
+                side.loaded.rows.len(),
+                selector.source
+            );
+            sources.insert(selector.source, side.loaded);
+        }
     }
+
+    let mut composed = compose::compose(&selectors, |source| &sources[source])?;
+    composed.rename_duplicate_paths(&[]);
+    progress!(
+        ts,
+        "composed {} glyphs over {} rows",
+        composed.len(),
+        composed.rows.len()
+    );
+
+    /* the game finds icons by counting, so check the rows still line up */
+    let index = composed.to_index();
+    let icons = match check_xml {
+        Some(assets) => XmlIcons::scan(assets)?,
+        None => XmlIcons::default(),
+    };
+    let lengths = composed.rows.iter().map(Vec::len).collect::<Vec<_>>();
+    let mut problems = check::check_row_lengths(&lengths);
+    problems.extend(check::check_loaded(&composed));
+    problems.extend(icons.check(&index));
+    problems.iter().for_each(report::problem);
+    let errors = check::count_errors(&problems);
+    if errors > 0 {
+        return Err(report::failed(
+            Class::Invalid,
+            format!("the composed rows aren't laid out like the game expects, {errors} errors"),
+        ));
+    }
+
+    if dry_run {
+        progress!(ts, "dry run, not saving to {destination}");
+        return Ok(());
+    }
+
+    progress!(ts, "saving to {destination}...");
+    fs::create_dir_all(destination).context("open destination")?;
+    composed.save_images(destination)?;
+    write_index(&ts, destination, &index, index_format)?;
+
+    Ok(())
+}
+
+/// Like `read_index`, but with images, and `input` can also be a manifest file.
+fn read_side(input: &str, stock_names: bool) -> Result<diff::Side> {
+    if !is_tga_path(input) {
+        let (root, _, index) = Index::read_from_path(input)?;
+        return Ok(diff::Side {
+            loaded: index.load_images(root)?,
+            paths_are_names: true,
+        });
+    }
+
+    let buf = image::open(input)
+        .with_context(|| format!("open {input}"))?
+        .into_rgba8();
+    let atlas = Atlas::from_image(&buf);
+    let mut index = atlas.to_index();
+    if stock_names {
+        layout::annotate_stock(&mut index);
+    }
+    Ok(diff::Side {
+        loaded: atlas.load_images(index),
+        paths_are_names: false,
+    })
 }
 
-use std::{cell::Cell, fmt, time::Instant};
+/// An unpacked directory, or a .tga that's unpacked in memory and named like `unpack` would.
+fn read_index(input: &str, stock_names: bool) -> Result<Index> {
+    if !is_tga_path(input) {
+        return Ok(Index::read_from_dir(input)?.1);
+    }
 
-struct TimeSince(Cell<Option<Instant>>);
+    let buf = image::open(input)
+        .with_context(|| format!("open {input}"))?
+        .into_rgba8();
+    let mut index = Atlas::from_image(&buf).to_index();
+    if stock_names {
+        layout::annotate_stock(&mut index);
+    }
+    Ok(index)
+}
 
-impl Default for TimeSince {
-    fn default() -> Self {
-        TimeSince(Cell::new(None))
+/// `Index::read_from_dir`, warning about an index.html that was saved after the manifest that's
+/// read instead, since edits to it are ignored.
+fn read_index_dir(dir: &str) -> Result<(IndexFormat, Index)> {
+    let (format, index) = Index::read_from_dir(dir)?;
+    if let Some(html) = format.newer_html(dir) {
+        warning!(
+            "{} was saved after {}, which is read instead, so changes to it are ignored",
+            html.display(),
+            format.path_in(dir).display()
+        );
     }
+    Ok((format, index))
 }
 
-impl fmt::Display for TimeSince {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let now = Instant::now();
-        let since = self
-            .0
-            .replace(Some(now))
-            .map(|then| now.saturating_duration_since(then).as_secs_f32())
-            .unwrap_or_default();
-        write!(f, "[{since:.03}s]")
+/// Write the index to `dir` and warn if `pack` would read a different manifest from there.
+fn write_index(ts: &TimeSince, dir: &str, index: &Index, format: IndexFormat) -> Result<()> {
+    progress!(ts, "writing {}", format.path_in(dir).display());
+    index.write_to_dir(dir, format)?;
+
+    if let Some(detected) = IndexFormat::detect(dir).filter(|&detected| detected != format) {
+        progress!(
+            ts,
+            "{} also exists and will be used instead by pack",
+            detected.path_in(dir).display()
+        );
     }
+
+    Ok(())
+}
+
+fn is_tga_path(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("tga"))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::atlas::{Atlas, Glyph};
-    use crate::point::Point;
-    use image::{io::Reader as ImageReader, ImageFormat};
+    use super::{cli, run};
+    use atlast::atlas::{Atlas, Glyph};
+    use atlast::point::Point;
+    use clap::Parser;
+    use image::{ImageFormat, ImageReader};
     use std::io::Cursor;
 
     const TGA: &[u8] = include_bytes!("../test.tga");
@@ -349,4 +1237,28 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_unpack_pack() {
+        let dir = std::env::temp_dir().join(format!("atlast-main-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+        std::fs::write(path("test.tga"), TGA).unwrap();
+        let atlast = |args: &[&str]| {
+            let argv = ["atlast"].iter().chain(args);
+            run(cli::Cli::try_parse_from(argv).unwrap().command).unwrap()
+        };
+
+        atlast(&["unpack", &path("test.tga"), "-o", &path("test")]);
+        atlast(&[
+            "pack",
+            &path("test"),
+            "-o",
+            &path("out.tga"),
+            "--keep-layout",
+        ]);
+        assert!(std::fs::read(path("out.tga")).unwrap() == TGA);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,372 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::index::{Index, IndexGlyph};
+use crate::Error;
+
+/// Why a manifest couldn't be parsed or written, see `Error::Manifest`.
+#[derive(Debug, thiserror::Error)]
+pub enum ManifestError {
+    #[error(transparent)]
+    Html(#[from] tl::ParseError),
+    #[error("img missing src attribute?")]
+    MissingSrc,
+    /// An index.html attribute with a value that doesn't parse.
+    #[error("expected {expected}, found: {found}")]
+    Attribute {
+        expected: &'static str,
+        found: String,
+    },
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    TomlRead(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlWrite(#[from] toml::ser::Error),
+}
+
+/// The ways an `Index` can be written to an unpacked directory.
+///
+/// index.html is the original and can be previewed in a browser. index.json and index.toml hold
+/// the same rows and glyphs but are easier to diff and script, and get a preview.html instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexFormat {
+    Html,
+    Json,
+    Toml,
+}
+
+impl IndexFormat {
+    /// In order of preference when more than one is in a directory. index.html is last because
+    /// older versions wrote it next to the others as a preview.
+    pub const DETECT_ORDER: [IndexFormat; 3] =
+        [IndexFormat::Toml, IndexFormat::Json, IndexFormat::Html];
+
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "html" => Some(IndexFormat::Html),
+            "json" => Some(IndexFormat::Json),
+            "toml" => Some(IndexFormat::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            IndexFormat::Html => "index.html",
+            IndexFormat::Json => "index.json",
+            IndexFormat::Toml => "index.toml",
+        }
+    }
+
+    pub fn path_in<P: AsRef<Path>>(self, dir: P) -> PathBuf {
+        dir.as_ref().join(self.file_name())
+    }
+
+    /// The first manifest found in `dir`, in `DETECT_ORDER`.
+    pub fn detect<P: AsRef<Path>>(dir: P) -> Option<Self> {
+        Self::DETECT_ORDER
+            .into_iter()
+            .find(|format| format.path_in(&dir).is_file())
+    }
+
+    /// An index.html in `dir` that was saved after the manifest in this format, so edits to it
+    /// are probably meant for packing but would be ignored.
+    pub fn newer_html<P: AsRef<Path>>(self, dir: P) -> Option<PathBuf> {
+        let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        let html = IndexFormat::Html.path_in(&dir);
+        let newer = self != IndexFormat::Html
+            && modified(&html)
+                .zip(modified(&self.path_in(&dir)))
+                .is_some_and(|(html, manifest)| html > manifest);
+        newer.then_some(html)
+    }
+}
+
+/// Where json and toml manifests put a page to see the atlas in a browser. It's not called
+/// index.html so that it's never read as the manifest.
+pub const PREVIEW_FILE_NAME: &str = "preview.html";
+
+impl fmt::Display for IndexFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IndexFormat::Html => "html",
+            IndexFormat::Json => "json",
+            IndexFormat::Toml => "toml",
+        })
+    }
+}
+
+/// What's actually serialized. TOML can't do arrays of arrays of tables, so each row is a table
+/// with its glyphs in it.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Manifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<(u32, u32)>,
+    rows: Vec<ManifestRow>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ManifestRow {
+    #[serde(default)]
+    glyphs: Vec<IndexGlyph>,
+}
+
+impl From<&Index> for Manifest {
+    fn from(index: &Index) -> Self {
+        let rows = index
+            .rows
+            .iter()
+            .map(|glyphs| ManifestRow {
+                glyphs: glyphs.clone(),
+            })
+            .collect();
+        Manifest {
+            size: index.size,
+            rows,
+        }
+    }
+}
+
+impl From<Manifest> for Index {
+    fn from(manifest: Manifest) -> Self {
+        let rows = manifest.rows.into_iter().map(|row| row.glyphs).collect();
+        Index {
+            rows,
+            size: manifest.size,
+        }
+    }
+}
+
+impl Index {
+    pub fn to_json(&self) -> Result<String, ManifestError> {
+        let mut s = serde_json::to_string_pretty(&Manifest::from(self))?;
+        s.push('\n');
+        Ok(s)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, ManifestError> {
+        Ok(serde_json::from_str::<Manifest>(s)?.into())
+    }
+
+    pub fn to_toml(&self) -> Result<String, ManifestError> {
+        Ok(toml::to_string(&Manifest::from(self))?)
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, ManifestError> {
+        Ok(toml::from_str::<Manifest>(s)?.into())
+    }
+
+    pub fn serialize(&self, format: IndexFormat) -> Result<String, ManifestError> {
+        match format {
+            IndexFormat::Html => Ok(self.to_html()),
+            IndexFormat::Json => self.to_json(),
+            IndexFormat::Toml => self.to_toml(),
+        }
+    }
+
+    pub fn deserialize(s: &str, format: IndexFormat) -> Result<Self, ManifestError> {
+        match format {
+            IndexFormat::Html => Self::from_html(s),
+            IndexFormat::Json => Self::from_json(s),
+            IndexFormat::Toml => Self::from_toml(s),
+        }
+    }
+
+    /// Read whichever manifest is in `dir`, see `IndexFormat::detect`.
+    pub fn read_from_dir<P: AsRef<Path>>(dir: P) -> Result<(IndexFormat, Self), Error> {
+        let format = IndexFormat::detect(&dir).ok_or_else(|| Error::NoManifest {
+            dir: dir.as_ref().to_owned(),
+        })?;
+        let index = Self::read_file(&format.path_in(&dir), format)?;
+        Ok((format, index))
+    }
+
+    /// Like `read_from_dir`, but `path` can also be a manifest file like `GameFont/index.json`.
+    /// Also returns the directory that glyph paths are relative to.
+    pub fn read_from_path<P: AsRef<Path>>(path: P) -> Result<(PathBuf, IndexFormat, Self), Error> {
+        let path = path.as_ref();
+        let format = path
+            .file_name()
+            .and_then(|name| {
+                IndexFormat::DETECT_ORDER
+                    .into_iter()
+                    .find(|format| name == format.file_name())
+            })
+            .filter(|_| path.is_file());
+        let Some(format) = format else {
+            let (format, index) = Self::read_from_dir(path)?;
+            return Ok((path.to_owned(), format, index));
+        };
+
+        let dir = path.parent().unwrap_or(Path::new("")).to_owned();
+        Ok((dir, format, Self::read_file(path, format)?))
+    }
+
+    fn read_file(path: &Path, format: IndexFormat) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path).map_err(|source| Error::Io {
+            action: "read",
+            path: path.to_owned(),
+            source,
+        })?;
+        Self::deserialize(&contents, format).map_err(|source| Error::Manifest {
+            path: path.to_owned(),
+            format,
+            source,
+        })
+    }
+
+    /// Write the manifest to `dir` in the given format. For formats other than html, also write
+    /// a `PREVIEW_FILE_NAME` page. Returns the paths written.
+    pub fn write_to_dir<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        format: IndexFormat,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let path = format.path_in(&dir);
+        let contents = self.serialize(format).map_err(|source| Error::Manifest {
+            path: path.clone(),
+            format,
+            source,
+        })?;
+        let mut files = vec![(path, contents)];
+        if format != IndexFormat::Html {
+            files.push((dir.as_ref().join(PREVIEW_FILE_NAME), self.to_html()));
+        }
+        files
+            .into_iter()
+            .map(|(path, contents)| match fs::write(&path, contents) {
+                Ok(()) => Ok(path),
+                Err(source) => Err(Error::Io {
+                    action: "write",
+                    path,
+                    source,
+                }),
+            })
+            .collect()
+    }
+
+    /// For glyphs in `self` with the same path as a glyph in `other`, update the descent and
+    /// position from `other` with `IndexGlyph::patch_from`, and take `other`'s size. Returns the
+    /// number of glyphs matched.
+    ///
+    /// This is `patch_html()` but for a parsed `Index`.
+    pub fn patch(&mut self, other: &Index) -> usize {
+        self.size = other.size.or(self.size);
+        let mut patches = other
+            .rows
+            .iter()
+            .flat_map(|row| row.iter())
+            .map(|glyph| (glyph.path.as_str(), glyph))
+            .collect::<Vec<_>>();
+        patches.sort_by_key(|&(path, _)| path);
+
+        self.rows
+            .iter_mut()
+            .flat_map(|row| row.iter_mut())
+            .filter_map(|glyph| {
+                let i = patches
+                    .binary_search_by(|&(path, _)| path.cmp(glyph.path.as_str()))
+                    .ok()?;
+                glyph.patch_from(patches[i].1);
+                Some(())
+            })
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_formats() {
+        let mut gold = IndexGlyph {
+            path: "001.png".into(),
+            descent: 0,
+            ..IndexGlyph::default()
+        };
+        gold.name = Some("gold".into());
+        gold.tags = vec!["yield".into(), "commerce".into()];
+        gold.meta
+            .insert("note".into(), "it's <gold> & stuff".into());
+        let index = Index {
+            rows: vec![
+                vec![IndexGlyph {
+                    path: "000.png".into(),
+                    descent: 4,
+                    char: Some('\''),
+                    at: Some((0, 0)),
+                    ..IndexGlyph::default()
+                }],
+                vec![gold],
+            ],
+            size: Some((730, 180)),
+        };
+
+        for format in IndexFormat::DETECT_ORDER {
+            let s = index.serialize(format).unwrap();
+            let parsed = Index::deserialize(&s, format).unwrap();
+            assert_eq!(parsed.rows, index.rows, "{format}");
+            assert_eq!(parsed.size, index.size, "{format}");
+        }
+    }
+
+    #[test]
+    fn test_reserved_meta_keys() {
+        let mut glyph = IndexGlyph {
+            path: "000.png".into(),
+            descent: 2,
+            ..IndexGlyph::default()
+        };
+        glyph.meta.insert("descent".into(), "7".into());
+        glyph.meta.insert("note".into(), "kept".into());
+        let html = Index {
+            rows: vec![vec![glyph]],
+            size: None,
+        }
+        .to_html();
+        assert_eq!(html.matches("data-descent").count(), 1);
+        let parsed = Index::from_html(&html).unwrap();
+        assert_eq!(parsed.rows[0][0].descent, 2);
+        assert_eq!(parsed.rows[0][0].meta.len(), 1);
+    }
+
+    #[test]
+    fn test_write_to_dir() {
+        let dir = std::env::temp_dir().join(format!("atlast-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let index = Index {
+            rows: vec![vec![IndexGlyph {
+                path: "000.png".into(),
+                ..IndexGlyph::default()
+            }]],
+            size: None,
+        };
+
+        let written = index.write_to_dir(&dir, IndexFormat::Toml).unwrap();
+        assert_eq!(written[1], dir.join(PREVIEW_FILE_NAME));
+        assert_eq!(IndexFormat::detect(&dir), Some(IndexFormat::Toml));
+        assert_eq!(IndexFormat::Toml.newer_html(&dir), None);
+
+        /* an index.html left over from before, edited after the manifest */
+        index.write_to_dir(&dir, IndexFormat::Html).unwrap();
+        let later = fs::metadata(IndexFormat::Toml.path_in(&dir))
+            .and_then(|meta| meta.modified())
+            .unwrap()
+            + std::time::Duration::from_secs(1);
+        fs::File::options()
+            .write(true)
+            .open(IndexFormat::Html.path_in(&dir))
+            .and_then(|file| file.set_modified(later))
+            .unwrap();
+        assert_eq!(
+            IndexFormat::Toml.newer_html(&dir),
+            Some(IndexFormat::Html.path_in(&dir))
+        );
+        assert_eq!(IndexFormat::Html.newer_html(&dir), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+
+use crate::diff::{self, Side, Slot};
+use crate::index::{unused_path, LoadedGlyph, LoadedIndex};
+
+/// The `meta` key conflicting glyphs are marked with, so `data-conflict` in index.html.
+pub const CONFLICT: &str = "conflict";
+
+pub struct Merged {
+    pub loaded: LoadedIndex,
+    /// Images that aren't in the index, like their version of a glyph both sides changed.
+    pub extra: Vec<LoadedGlyph>,
+    pub conflicts: Vec<(Slot, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Who {
+    Ours,
+    Theirs,
+}
+
+impl Who {
+    fn as_str(self) -> &'static str {
+        match self {
+            Who::Ours => "ours",
+            Who::Theirs => "theirs",
+        }
+    }
+}
+
+/// What one side did to a glyph from the base.
+#[derive(Debug, Clone, Copy)]
+enum Fate {
+    Removed,
+    InPlace(Slot),
+    Moved(Slot),
+}
+
+/// A row of one side, and the column in the base of the glyph an insertion follows, or `None` at
+/// the start.
+type Spot = (usize, Option<usize>);
+
+/// How one side differs from the base.
+struct Changes<'a> {
+    who: Who,
+    glyphs: HashMap<Slot, &'a LoadedGlyph>,
+    fates: HashMap<Slot, Fate>,
+    /// The row of this side each row of the base became, `None` if it was removed.
+    rows: Vec<Option<usize>>,
+    /// Rows of this side that aren't from the base, by the base row they follow.
+    new_rows: HashMap<Option<usize>, Vec<usize>>,
+    /// Glyphs that aren't in place, by where they go. Each with the base glyph it was moved from,
+    /// if it was.
+    inserted: HashMap<Spot, Vec<(Slot, Option<Slot>)>>,
+}
+
+impl<'a> Changes<'a> {
+    fn new(who: Who, base: &Side, side: &'a Side) -> Self {
+        let pairs = diff::diff(base, side)
+            .into_iter()
+            .filter_map(|diff| Some((diff.old?, diff.new?)))
+            .collect::<Vec<_>>();
+        let rows = align_rows(base.loaded.rows.len(), side.loaded.rows.len(), &pairs);
+
+        /* in each pair of rows, the most glyphs that stayed in the same order are in place */
+        let mut fates = HashMap::new();
+        let mut moved_from = HashMap::new();
+        let mut in_place = HashMap::new();
+        for (new_row, _) in side.loaded.rows.iter().enumerate() {
+            let mut row = pairs
+                .iter()
+                .filter(|(old, new)| new.row == new_row && rows[old.row] == Some(new_row))
+                .copied()
+                .collect::<Vec<_>>();
+            row.sort_by_key(|(_, new)| new.col);
+            let order = row.iter().map(|(old, _)| old.col).collect::<Vec<_>>();
+            for (&(old, new), stayed) in row.iter().zip(diff::longest_increasing(&order)) {
+                if stayed {
+                    in_place.insert(new, old);
+                }
+            }
+        }
+        for &(old, new) in &pairs {
+            match in_place.get(&new) {
+                Some(_) => fates.insert(old, Fate::InPlace(new)),
+                None => {
+                    moved_from.insert(new, old);
+                    fates.insert(old, Fate::Moved(new))
+                }
+            };
+        }
+
+        let mut base_row_of = vec![None; side.loaded.rows.len()];
+        for (base_row, row) in rows.iter().enumerate() {
+            if let Some(row) = *row {
+                base_row_of[row] = Some(base_row);
+            }
+        }
+        let mut new_rows = HashMap::<_, Vec<_>>::new();
+        let mut after_row = None;
+        for (row, base_row) in base_row_of.iter().enumerate() {
+            match base_row {
+                Some(_) => after_row = *base_row,
+                None => new_rows.entry(after_row).or_default().push(row),
+            }
+        }
+
+        let glyphs = diff::slots(&side.loaded);
+        let mut inserted = HashMap::<_, Vec<_>>::new();
+        let mut after = None;
+        for (i, (slot, _)) in glyphs.iter().enumerate() {
+            if i == 0 || glyphs[i - 1].0.row != slot.row {
+                after = None;
+            }
+            match in_place.get(slot) {
+                Some(old) => after = Some(old.col),
+                None => inserted
+                    .entry((slot.row, after))
+                    .or_default()
+                    .push((*slot, moved_from.get(slot).copied())),
+            }
+        }
+
+        Changes {
+            who,
+            glyphs: glyphs.into_iter().collect(),
+            fates,
+            rows,
+            new_rows,
+            inserted,
+        }
+    }
+
+    fn fate(&self, base: Slot) -> Fate {
+        self.fates.get(&base).copied().unwrap_or(Fate::Removed)
+    }
+
+    fn glyph(&self, fate: Fate) -> Option<&'a LoadedGlyph> {
+        match fate {
+            Fate::Removed => None,
+            Fate::InPlace(slot) | Fate::Moved(slot) => Some(self.glyphs[&slot]),
+        }
+    }
+}
+
+/// Where a glyph from the base ends up, and as what.
+enum Resolution {
+    Removed,
+    InPlace(LoadedGlyph, Option<String>),
+    MovedBy(Who, Slot, LoadedGlyph, Option<String>),
+}
+
+/// Merge the changes `ours` and `theirs` made to `base`, like git does for lines of text.
+///
+/// Glyphs are matched up like `diff` does, and rows by the glyphs they share, see `align_rows`.
+/// Glyphs and rows inserted by one side go after the same glyph or row from the base as they did
+/// on that side, and rows left empty are dropped. A glyph changed by one side, meaning its image or
+/// anything in the manifest other than its path, takes that side's version. A glyph that both
+/// sides changed differently, that one side changed and the other removed, or where both sides
+/// inserted different glyphs or rows at the same spot, is a conflict. Conflicts keep ours, or both of
+/// the insertions, and are marked in `meta` with `CONFLICT`.
+pub fn merge(base: &Side, ours: &Side, theirs: &Side) -> Merged {
+    let ours = Changes::new(Who::Ours, base, ours);
+    let theirs = Changes::new(Who::Theirs, base, theirs);
+    let mut extra = Vec::new();
+
+    let resolutions = diff::slots(&base.loaded)
+        .into_iter()
+        .map(|(slot, base)| (slot, resolve(slot, base, &ours, &theirs, &mut extra)))
+        .collect::<HashMap<_, _>>();
+
+    let mut merged = Vec::new();
+    new_rows(None, &ours, &theirs, &resolutions, &mut merged);
+    for (row, base_row) in base.loaded.rows.iter().enumerate() {
+        let mut glyphs = Vec::new();
+        insertions(row, None, &ours, &theirs, &resolutions, &mut glyphs);
+        for col in 0..base_row.len() {
+            if let Resolution::InPlace(glyph, conflict) = &resolutions[&Slot { row, col }] {
+                glyphs.push(marked(glyph, conflict.as_deref()));
+            }
+            insertions(row, Some(col), &ours, &theirs, &resolutions, &mut glyphs);
+        }
+        merged.push(glyphs);
+        new_rows(Some(row), &ours, &theirs, &resolutions, &mut merged);
+    }
+    /* rows that lost all their glyphs */
+    merged.retain(|glyphs| !glyphs.is_empty());
+
+    let mut merged = LoadedIndex { rows: merged };
+    let reserved = extra
+        .iter()
+        .map(|loaded| loaded.glyph.path.clone())
+        .collect::<Vec<_>>();
+    merged.rename_duplicate_paths(&reserved);
+
+    let conflicts = merged
+        .rows
+        .iter()
+        .enumerate()
+        .flat_map(|(row, glyphs)| {
+            glyphs.iter().enumerate().filter_map(move |(col, loaded)| {
+                let conflict = loaded.glyph.meta.get(CONFLICT)?;
+                Some((Slot { row, col }, conflict.clone()))
+            })
+        })
+        .collect();
+
+    Merged {
+        loaded: merged,
+        extra,
+        conflicts,
+    }
+}
+
+fn resolve(
+    slot: Slot,
+    base: &LoadedGlyph,
+    ours: &Changes,
+    theirs: &Changes,
+    extra: &mut Vec<LoadedGlyph>,
+) -> Resolution {
+    let (our_fate, their_fate) = (ours.fate(slot), theirs.fate(slot));
+    let (our_glyph, their_glyph) = (ours.glyph(our_fate), theirs.glyph(their_fate));
+    let changed = |glyph: Option<&LoadedGlyph>| glyph.is_some_and(|glyph| !same(glyph, base));
+
+    let (glyph, conflict) = match (our_glyph, their_glyph) {
+        (None, None) => return Resolution::Removed,
+        (Some(kept), None) | (None, Some(kept)) => {
+            if !changed(Some(kept)) {
+                return Resolution::Removed;
+            }
+            let (changer, remover) = match our_glyph {
+                Some(_) => (Who::Ours, Who::Theirs),
+                None => (Who::Theirs, Who::Ours),
+            };
+            let conflict = format!(
+                "changed by {} but removed by {}",
+                changer.as_str(),
+                remover.as_str()
+            );
+            (kept.clone(), Some(conflict))
+        }
+        (Some(our_glyph), Some(their_glyph)) => {
+            if !changed(Some(our_glyph)) {
+                (their_glyph.clone(), None)
+            } else if !changed(Some(their_glyph)) || same(our_glyph, their_glyph) {
+                (our_glyph.clone(), None)
+            } else {
+                let path = unused_path(&our_glyph.glyph.path, ".theirs", &[]);
+                let mut theirs = their_glyph.clone();
+                theirs.glyph.path.clone_from(&path);
+                extra.push(theirs);
+                let conflict = format!("changed by both, theirs is {path}");
+                (our_glyph.clone(), Some(conflict))
+            }
+        }
+    };
+
+    /* a move by either side wins over staying in place, and ours wins over theirs */
+    match (our_fate, their_fate) {
+        (Fate::Moved(ours_to), Fate::Moved(_)) => {
+            let conflict = conflict.or(Some("moved by both, kept where ours put it".to_owned()));
+            Resolution::MovedBy(Who::Ours, ours_to, glyph, conflict)
+        }
+        (Fate::Moved(to), _) => Resolution::MovedBy(Who::Ours, to, glyph, conflict),
+        (_, Fate::Moved(to)) => Resolution::MovedBy(Who::Theirs, to, glyph, conflict),
+        _ => Resolution::InPlace(glyph, conflict),
+    }
+}
+
+/// Add what either side inserted after column `after` of the base's `row`.
+fn insertions(
+    row: usize,
+    after: Option<usize>,
+    ours: &Changes,
+    theirs: &Changes,
+    resolutions: &HashMap<Slot, Resolution>,
+    glyphs: &mut Vec<LoadedGlyph>,
+) {
+    let inserted = |changes: &Changes| match changes.rows[row] {
+        Some(side_row) => inserted(changes, (side_row, after), resolutions),
+        None => Vec::new(),
+    };
+    let conflict = "both inserted glyphs here, ours are first";
+    glyphs.extend(combine(inserted(ours), inserted(theirs), conflict));
+}
+
+/// Add the rows either side inserted after the base's row `after`, or before the first.
+fn new_rows(
+    after: Option<usize>,
+    ours: &Changes,
+    theirs: &Changes,
+    resolutions: &HashMap<Slot, Resolution>,
+    rows: &mut Vec<Vec<LoadedGlyph>>,
+) {
+    /* each row as one list of glyphs with where it ends, so they can be compared and marked */
+    let inserted = |changes: &Changes| {
+        let mut glyphs = Vec::new();
+        let mut ends = Vec::new();
+        for &row in changes.new_rows.get(&after).into_iter().flatten() {
+            glyphs.extend(inserted(changes, (row, None), resolutions));
+            ends.push(glyphs.len());
+        }
+        (glyphs, ends)
+    };
+    let ((ours, our_ends), (theirs, their_ends)) = (inserted(ours), inserted(theirs));
+    let ends = match (ours.is_empty(), theirs.is_empty()) {
+        (true, _) => their_ends,
+        (false, true) => our_ends,
+        (false, false) if same_glyphs(&ours, &theirs) => our_ends,
+        (false, false) => {
+            let start = ours.len();
+            our_ends
+                .into_iter()
+                .chain(their_ends.into_iter().map(|end| start + end))
+                .collect()
+        }
+    };
+    let mut glyphs = combine(ours, theirs, "both inserted rows here, ours are first").into_iter();
+    let mut start = 0;
+    for end in ends {
+        rows.push(glyphs.by_ref().take(end - start).collect());
+        start = end;
+    }
+}
+
+/// What one side inserted at `spot`, leaving out glyphs moved there that the merge put elsewhere.
+fn inserted(
+    changes: &Changes,
+    spot: Spot,
+    resolutions: &HashMap<Slot, Resolution>,
+) -> Vec<(LoadedGlyph, Option<String>)> {
+    let Some(inserted) = changes.inserted.get(&spot) else {
+        return Vec::new();
+    };
+    inserted
+        .iter()
+        .filter_map(|&(slot, from)| {
+            let Some(from) = from else {
+                return Some((changes.glyphs[&slot].clone(), None));
+            };
+            match &resolutions[&from] {
+                Resolution::MovedBy(who, to, glyph, conflict)
+                    if *who == changes.who && *to == slot =>
+                {
+                    Some((glyph.clone(), conflict.clone()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Both sides' insertions at the same spot: one side's if the other didn't insert anything or
+/// inserted the same, otherwise both, marked with `conflict`.
+fn combine(
+    ours: Vec<(LoadedGlyph, Option<String>)>,
+    theirs: Vec<(LoadedGlyph, Option<String>)>,
+    conflict: &str,
+) -> Vec<LoadedGlyph> {
+    if ours.is_empty() || theirs.is_empty() || same_glyphs(&ours, &theirs) {
+        let side = if ours.is_empty() { theirs } else { ours };
+        return side
+            .iter()
+            .map(|(glyph, conflict)| marked(glyph, conflict.as_deref()))
+            .collect();
+    }
+    ours.iter()
+        .chain(&theirs)
+        .map(|(glyph, other)| marked(glyph, Some(other.as_deref().unwrap_or(conflict))))
+        .collect()
+}
+
+fn same_glyphs(
+    ours: &[(LoadedGlyph, Option<String>)],
+    theirs: &[(LoadedGlyph, Option<String>)],
+) -> bool {
+    ours.len() == theirs.len()
+        && ours
+            .iter()
+            .zip(theirs)
+            .all(|((ours, _), (theirs, _))| same(ours, theirs))
+}
+
+/// Which row of the other side each of the `base_rows` rows became, pairing up the rows that
+/// share the most glyphs without changing their order, like `longest_increasing` does for glyphs.
+fn align_rows(base_rows: usize, rows: usize, pairs: &[(Slot, Slot)]) -> Vec<Option<usize>> {
+    let mut shared = vec![vec![0; rows]; base_rows];
+    for (old, new) in pairs {
+        shared[old.row][new.row] += 1;
+    }
+    /* best[i][j] is the most glyphs kept by aligning base rows i.. with rows j.. */
+    let mut best = vec![vec![0; rows + 1]; base_rows + 1];
+    for i in (0..base_rows).rev() {
+        for j in (0..rows).rev() {
+            let paired = match shared[i][j] {
+                0 => 0,
+                n => n + best[i + 1][j + 1],
+            };
+            best[i][j] = paired.max(best[i + 1][j]).max(best[i][j + 1]);
+        }
+    }
+    let mut aligned = vec![None; base_rows];
+    let (mut i, mut j) = (0, 0);
+    while i < base_rows && j < rows {
+        if shared[i][j] > 0 && best[i][j] == shared[i][j] + best[i + 1][j + 1] {
+            aligned[i] = Some(j);
+            (i, j) = (i + 1, j + 1);
+        } else if best[i][j] == best[i + 1][j] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    aligned
+}
+
+fn marked(loaded: &LoadedGlyph, conflict: Option<&str>) -> LoadedGlyph {
+    let mut loaded = loaded.clone();
+    if let Some(conflict) = conflict {
+        loaded
+            .glyph
+            .meta
+            .insert(CONFLICT.to_owned(), conflict.to_owned());
+    }
+    loaded
+}
+
+/// The same image and manifest entry, not counting its path.
+fn same(a: &LoadedGlyph, b: &LoadedGlyph) -> bool {
+    let mut b_glyph = b.glyph.clone();
+    b_glyph.path.clone_from(&a.glyph.path);
+    a.image == b.image && a.glyph == b_glyph
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::IndexGlyph;
+    use image::{Rgba, RgbaImage};
+
+    fn side(rows: &[&[(char, u8)]]) -> Side {
+        let rows = rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&(c, shade)| LoadedGlyph {
+                        glyph: IndexGlyph {
+                            path: format!("{c}.png"),
+                            char: Some(c),
+                            ..IndexGlyph::default()
+                        },
+                        image: RgbaImage::from_pixel(2, 2, Rgba([shade, shade, shade, 255])),
+                    })
+                    .collect()
+            })
+            .collect();
+        Side {
+            loaded: LoadedIndex { rows },
+            paths_are_names: true,
+        }
+    }
+
+    fn chars(merged: &Merged) -> Vec<String> {
+        merged
+            .loaded
+            .rows
+            .iter()
+            .map(|row| row.iter().filter_map(|l| l.glyph.char).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = side(&[&[('a', 0), ('b', 0), ('c', 0)], &[('x', 0), ('y', 0)]]);
+        /* inserts d after a, changes x */
+        let ours = side(&[
+            &[('a', 0), ('d', 0), ('b', 0), ('c', 0)],
+            &[('x', 1), ('y', 0)],
+        ]);
+        /* removes b, inserts e at the end, changes x differently, moves y to the first row */
+        let theirs = side(&[&[('y', 0), ('a', 0), ('c', 0), ('e', 0)], &[('x', 2)]]);
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(chars(&merged), ["yadce", "x"]);
+
+        assert_eq!(merged.conflicts.len(), 1, "{:?}", merged.conflicts);
+        assert_eq!(merged.conflicts[0].0, Slot { row: 1, col: 0 });
+        assert_eq!(merged.loaded.rows[1][0].image.get_pixel(0, 0)[0], 1);
+        assert_eq!(merged.extra[0].glyph.path, "x.theirs.png");
+        assert_eq!(merged.extra[0].image.get_pixel(0, 0)[0], 2);
+
+        /* ours inserts a row, theirs inserts a glyph in the row after it */
+        let base = side(&[&[('a', 0), ('b', 0)], &[('x', 0), ('y', 0)]]);
+        let ours = side(&[&[('a', 0), ('b', 0)], &[('n', 0)], &[('x', 0), ('y', 0)]]);
+        let theirs = side(&[&[('a', 0), ('b', 0)], &[('x', 0), ('z', 0), ('y', 0)]]);
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(chars(&merged), ["ab", "n", "xzy"]);
+        assert!(merged.conflicts.is_empty(), "{:?}", merged.conflicts);
+
+        /* theirs removes a row and both insert a different row at the end */
+        let ours = side(&[&[('a', 0), ('b', 0)], &[('x', 0), ('y', 0)], &[('n', 0)]]);
+        let theirs = side(&[&[('x', 0), ('y', 0)], &[('m', 0)]]);
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(chars(&merged), ["xy", "n", "m"]);
+        assert_eq!(merged.conflicts.len(), 2, "{:?}", merged.conflicts);
+    }
+}
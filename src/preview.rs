@@ -0,0 +1,188 @@
+//! The page `atlast serve` shows: the index's listing with the packed atlas above it, and a
+//! script to edit the listing that posts each change back to the server.
+
+use image::{Rgba, RgbaImage};
+
+use crate::index::{html_encoded, Index, BASELINE, FRAME};
+
+/// Frame pixels are see-through, so make them a pink tint and baseline markers solid cyan.
+pub fn show_frame(atlas: &RgbaImage) -> RgbaImage {
+    let mut shown = atlas.clone();
+    for pixel in shown.pixels_mut() {
+        if *pixel == FRAME {
+            *pixel = Rgba([255, 0, 255, 96]);
+        } else if *pixel == BASELINE {
+            *pixel = Rgba([0, 255, 255, 255]);
+        }
+    }
+    shown
+}
+
+/// `Index::to_html` with the atlas from `atlas.png` above the listing, and a script that reloads
+/// the page when `changes?since={version}` answers with a different version. `error` is shown
+/// at the top, for when the directory couldn't be read again.
+///
+/// Each change is posted with `token` in an `X-Atlast-Token` header, so the server can tell the
+/// page it served from any other page in the browser.
+///
+/// Glyphs can be dragged onto another glyph to go before it, onto a row to go at its end, or
+/// below the listing for a new row, which posts `move?from=ROW,GLYPH&to=ROW,GLYPH`. Clicking a
+/// pixel posts `descent?glyph=ROW,GLYPH&row=Y` to put the baseline marker beside its row, see
+/// `descent_at_row`, and dropping an image file on a glyph posts it to `replace?glyph=ROW,GLYPH`.
+pub fn page(index: &Index, version: u64, error: Option<&str>, token: &str) -> String {
+    let error = error
+        .map(|error| format!("<pre id=error>{}</pre>\n", html_encoded(error)))
+        .unwrap_or_default();
+    let preview = format!(
+        r#"{STYLE}</head>
+{error}<div id=preview>
+<label><input type=checkbox id=frame> show frame and baseline pixels</label>
+<label><input type=checkbox id=baseline> click a pixel to put the baseline marker beside its row</label>
+<img id=atlas src="atlas.png?v={version}">
+</div>
+<script>
+const version = {version};
+const token = '{token}';
+const frame = document.getElementById('frame');
+const show = () => {{
+  document.getElementById('atlas').src = 'atlas.png?v=' + version + (frame.checked ? '&frame' : '');
+  history.replaceState(null, '', frame.checked ? '#frame' : '#');
+}};
+frame.checked = location.hash == '#frame';
+frame.onchange = show;
+show();
+(async () => {{
+  for (;;) {{
+    try {{
+      const res = await fetch('changes?since=' + version);
+      if (+(await res.text()) != version) return location.reload();
+    }} catch (e) {{
+      await new Promise(resolve => setTimeout(resolve, 1000));
+    }}
+  }}
+}})();
+</script>
+"#
+    );
+    index.to_html().replacen("</head>\n", &preview, 1) + EDIT
+}
+
+/// The descent that puts a glyph's baseline marker beside `row` of a glyph `height` tall, which
+/// is where `copy_glyph_to_atlas` draws it.
+pub fn descent_at_row(height: u32, row: u32) -> u32 {
+    height.saturating_sub(row)
+}
+
+const STYLE: &str = r#"<style>
+body
+  { color: #ddd; font-family: sans-serif }
+#preview
+  { margin-bottom: 1em }
+#atlas
+  { display: block; margin-top: 0.5em; image-rendering: pixelated;
+    scale: var(--ppiUnscale); transform-origin: top left }
+#error
+  { color: #f88 }
+[data-atlas=row] img
+  { zoom: 2; image-rendering: pixelated; cursor: grab }
+#new-row
+  { margin-top: 1em; padding: 1em; border: 1px dashed #666 }
+</style>
+"#;
+
+const EDIT: &str = r#"<div id=new-row>drop a glyph here for a new row</div>
+<script>
+const rows = [...document.querySelectorAll('[data-atlas=row]')];
+const slot = img => {
+  const row = img.closest('[data-atlas=row]');
+  return [rows.indexOf(row), [...row.querySelectorAll('img')].indexOf(img)];
+};
+const post = async (url, body) => {
+  const res = await fetch(url, { method: 'POST', body, headers: { 'X-Atlast-Token': token } });
+  if (!res.ok) alert(await res.text());
+};
+let dragged = null;
+const drop = (target, to) => {
+  target.ondragover = e => e.preventDefault();
+  target.ondrop = e => {
+    e.preventDefault();
+    e.stopPropagation();
+    const file = e.dataTransfer.files[0];
+    if (file && target.tagName == 'IMG') post(`replace?glyph=${to()}`, file);
+    else if (file) alert('drop it on the glyph to replace');
+    else if (dragged) post(`move?from=${dragged}&to=${to()}`);
+    dragged = null;
+  };
+};
+for (const row of rows) {
+  drop(row, () => [rows.indexOf(row), row.querySelectorAll('img').length]);
+  for (const img of row.querySelectorAll('img')) {
+    img.title = `${img.getAttribute('src')}, descent ${img.dataset.descent || 0}`;
+    img.ondragstart = () => { dragged = slot(img); };
+    img.onclick = e => {
+      if (!document.getElementById('baseline').checked) return;
+      const rect = img.getBoundingClientRect();
+      const y = Math.floor((e.clientY - rect.top) / rect.height * img.naturalHeight);
+      post(`descent?glyph=${slot(img)}&row=${y}`);
+    };
+    drop(img, () => slot(img));
+  }
+}
+drop(document.getElementById('new-row'), () => [rows.length, 0]);
+/* instead of opening a file dropped anywhere else */
+document.ondragover = e => e.preventDefault();
+document.ondrop = e => e.preventDefault();
+</script>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::{copy_glyph_to_atlas, IndexGlyph};
+    use crate::point::Point;
+
+    #[test]
+    fn test_preview() {
+        let atlas = RgbaImage::from_fn(3, 1, |x, _| match x {
+            0 => FRAME,
+            1 => BASELINE,
+            _ => Rgba([1, 2, 3, 4]),
+        });
+        let shown = show_frame(&atlas);
+        assert_eq!(shown.get_pixel(0, 0)[3], 96);
+        assert_eq!(*shown.get_pixel(1, 0), Rgba([0, 255, 255, 255]));
+        assert_eq!(*shown.get_pixel(2, 0), Rgba([1, 2, 3, 4]));
+
+        let index = Index {
+            rows: vec![vec![IndexGlyph {
+                path: "gold.png".to_owned(),
+                ..IndexGlyph::default()
+            }]],
+            size: None,
+        };
+        let html = page(&index, 7, Some("<oops>"), "f00d");
+        assert!(html.contains("<pre id=error>&lt;oops&gt;</pre>"));
+        assert!(html.contains("const version = 7;"));
+        assert!(html.contains("const token = 'f00d';"));
+        assert!(html.find("id=atlas") < html.find("gold.png"));
+        assert!(html.find("gold.png") < html.find("id=new-row"));
+        /* still reads as an index */
+        assert_eq!(Index::from_html(&html).unwrap(), index);
+    }
+
+    #[test]
+    fn test_descent_at_row() {
+        let glyph = RgbaImage::from_pixel(2, 5, Rgba([1, 2, 3, 4]));
+        for row in 0..5 {
+            let mut atlas = RgbaImage::from_pixel(3, 5, FRAME);
+            copy_glyph_to_atlas(
+                &mut atlas,
+                Point { x: 0, y: 0 },
+                &glyph,
+                descent_at_row(5, row),
+            );
+            let marked = (0..5).filter(|&y| *atlas.get_pixel(2, y) == BASELINE);
+            assert_eq!(marked.collect::<Vec<_>>(), [row]);
+        }
+    }
+}
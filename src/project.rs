@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use atlast::scale::Filter;
+
+use crate::cli::parse_dims;
+use crate::report::{failed, Class};
+
+/// Defaults for `pack` and `pack-pair` from an `atlast.toml` in the unpacked directory, so the
+/// size the game needs doesn't have to be remembered. Options on the command line win.
+#[derive(Debug, Default)]
+pub struct Project {
+    /// relative to the directory
+    pub output: Option<String>,
+    pub size: Option<(u32, u32)>,
+    pub size_75: Option<(u32, u32)>,
+    pub filter: Option<Filter>,
+    pub keep_layout: bool,
+    pub wrap: bool,
+    pub allow_clip: bool,
+    /// relative to the directory
+    pub check_xml: Option<String>,
+}
+
+/// What's actually in the file.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ProjectFile {
+    output: Option<String>,
+    size: Option<String>,
+    size_75: Option<String>,
+    filter: Option<String>,
+    #[serde(default)]
+    keep_layout: bool,
+    #[serde(default)]
+    wrap: bool,
+    #[serde(default)]
+    allow_clip: bool,
+    check_xml: Option<String>,
+}
+
+impl Project {
+    pub const FILE_NAME: &'static str = "atlast.toml";
+
+    /// The `atlast.toml` in `dir`, or `None` if there isn't one.
+    pub fn read_from_dir<P: AsRef<Path>>(dir: P) -> Result<Option<Self>> {
+        let dir = dir.as_ref();
+        let path = dir.join(Self::FILE_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let contents =
+            fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+        /* it stands in for arguments, so it's as if they were bad */
+        Self::from_toml(&contents, dir)
+            .map_err(|err| failed(Class::Args, format!("parse {}: {err:#}", path.display())))
+            .map(Some)
+    }
+
+    /// Paths are made relative to `dir` instead of the file.
+    fn from_toml(s: &str, dir: &Path) -> Result<Self> {
+        let file = toml::from_str::<ProjectFile>(s)?;
+        let dims = |key: &str, value: Option<String>| {
+            value
+                .map(|value| {
+                    parse_dims(&value).with_context(|| {
+                        format!("expected {key} = \"[WIDTH]x[HEIGHT]\", like \"2046x540\"")
+                    })
+                })
+                .transpose()
+        };
+        let path = |value: Option<String>| {
+            value.map(|value| dir.join(value).to_string_lossy().into_owned())
+        };
+        Ok(Project {
+            output: path(file.output),
+            size: dims("size", file.size)?,
+            size_75: dims("size-75", file.size_75)?,
+            filter: file
+                .filter
+                .map(|name| {
+                    Filter::from_name(&name).context("expected filter = lanczos, box, or nearest")
+                })
+                .transpose()?,
+            keep_layout: file.keep_layout,
+            wrap: file.wrap,
+            allow_clip: file.allow_clip,
+            check_xml: path(file.check_xml),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project() {
+        let dir = Path::new("GameFont");
+        let project = Project::from_toml(
+            "output = \"../GameFont.tga\"\nsize = \"2046x540\"\nsize-75 = \"x405\"\nkeep-layout = true\n",
+            dir,
+        )
+        .unwrap();
+        assert_eq!(
+            project.output.as_deref().map(Path::new),
+            Some(dir.join("../GameFont.tga").as_path())
+        );
+        assert_eq!(project.size, Some((2046, 540)));
+        assert_eq!(project.size_75, Some((0, 405)));
+        assert!(project.keep_layout && !project.wrap);
+        assert!(Project::from_toml("size = \"big\"", dir).is_err());
+        assert!(Project::from_toml("sise = \"2046x540\"", dir).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+use ab_glyph::{point, Font, GlyphId, PxScale, ScaleFont};
+use image::{Rgba, RgbaImage};
+
+use crate::index::{IndexGlyph, LoadedGlyph, LoadedIndex};
+use crate::Error;
+
+/// The printable characters in the text portion of the stock GameFont.tga, the same as the default
+/// text in atlast.html. Each line is a row in the atlas.
+pub const DEFAULT_TEXT: &str = "\
+!\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVW
+XYZ[×]^_`abcdefghijklmnopqrstuvwxyz{}~\\ẞÀÁÂÃÄÅÆÇÈÉÊËÌÍ
+ÎÏÐÑÒÓÔÕÖØÙÚÛÜÝÞŸßàáâãäåæçèéêëìíîïðñòóôõö÷øùúûüýþÿ¿¡«»°Š
+ŒŽšœž™©®€£¢“‘”…’";
+
+/// Options for rasterizing text, these correspond to the inputs of the same name in atlast.html.
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// font size in pixels, like `18px` in the font field of atlast.html
+    pub size: f32,
+    pub color: Rgba<u8>,
+    /// shrink the measured left side of each glyph by this many pixels
+    pub trim_left: f32,
+    /// shrink the measured right side of each glyph by this many pixels
+    pub trim_width: f32,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            size: 18.,
+            color: Rgba([255, 255, 255, 255]),
+            trim_left: 0.12,
+            trim_width: 0.12,
+        }
+    }
+}
+
+/// Measurements of a character, like what `measureText()` gives us in atlast.html.
+#[derive(Debug)]
+struct Metrics {
+    c: char,
+    id: GlyphId,
+    /// x offset from the left edge of the glyph image to where the character is drawn
+    left: f32,
+    width: u32,
+    height: u32,
+    /// distance from the bottom of the glyph image to the baseline
+    descent: u32,
+}
+
+/// Rasterize each character in `text` to its own image, following what `redraw()` in atlast.html
+/// does. Characters with no outline, like newlines, start a new row.
+///
+/// Glyphs in a row are all as tall as the tallest glyph in the text. Each glyph's path is named
+/// like the ones from `Atlas::save_images`.
+pub fn render_text<F: Font>(
+    font: &F,
+    text: &str,
+    options: &RenderOptions,
+) -> Result<LoadedIndex, Error> {
+    let scale = px_scale(font, options.size)?;
+    let scaled = font.as_scaled(scale);
+    let (h_scale, v_scale) = (scaled.h_scale_factor(), scaled.v_scale_factor());
+
+    let metrics = text
+        .chars()
+        .filter(|&c| c != '\r')
+        .map(|c| {
+            if c == '\n' {
+                return None;
+            }
+            let id = font.glyph_id(c);
+            let outline = font.outline(id)?;
+            /* outline bounds are unscaled with y going up, min is top-left */
+            let left_f = -outline.bounds.min.x * h_scale;
+            let right_f = outline.bounds.max.x * h_scale;
+            let ascent_f = outline.bounds.min.y * v_scale;
+            let descent_f = -outline.bounds.max.y * v_scale;
+            let left = (left_f - options.trim_left).ceil();
+            let width = left + (right_f - options.trim_width).ceil();
+            Some(Metrics {
+                c,
+                id,
+                left,
+                width: width.max(1.) as u32,
+                height: (ascent_f + descent_f).ceil().max(0.) as u32,
+                descent: descent_f.ceil().max(0.) as u32,
+            })
+        })
+        .collect::<Vec<Option<Metrics>>>();
+
+    let text_height = metrics
+        .iter()
+        .flatten()
+        .map(|m| m.height)
+        .max()
+        .unwrap_or(0);
+
+    let mut i = 0;
+    let rows = metrics
+        .split(Option::is_none)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.iter()
+                .flatten()
+                .map(|m| {
+                    let path = format!("{i:03}.png");
+                    i += 1;
+
+                    let mut image = RgbaImage::new(m.width, text_height);
+                    let baseline = text_height.saturating_sub(m.descent) as f32;
+                    let glyph = m.id.with_scale_and_position(scale, point(m.left, baseline));
+                    if let Some(outlined) = font.outline_glyph(glyph) {
+                        let bounds = outlined.px_bounds();
+                        outlined.draw(|x, y, coverage| {
+                            let x = bounds.min.x as i64 + x as i64;
+                            let y = bounds.min.y as i64 + y as i64;
+                            let (Ok(x), Ok(y)) = (u32::try_from(x), u32::try_from(y)) else {
+                                return;
+                            };
+                            if let Some(pixel) = image.get_pixel_mut_checked(x, y) {
+                                let Rgba([r, g, b, a]) = options.color;
+                                let a = (a as f32 * coverage.clamp(0., 1.)).round() as u8;
+                                *pixel = Rgba([r, g, b, a]);
+                            }
+                        });
+                    }
+
+                    /* The cyan marker in atlast.html is drawn one pixel above the baseline and
+                     * Atlas::from_image measures from the bottom frame, so off by one here. */
+                    let descent = match m.descent {
+                        0 => 0,
+                        d => d + 1,
+                    };
+
+                    LoadedGlyph {
+                        glyph: IndexGlyph {
+                            path,
+                            descent,
+                            char: Some(m.c),
+                            ..IndexGlyph::default()
+                        },
+                        image,
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    Ok(LoadedIndex { rows })
+}
+
+/// Characters in `text` that `font` doesn't have a glyph for.
+pub fn missing_chars<F: Font>(font: &F, text: &str) -> Vec<char> {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .filter(|&c| font.glyph_id(c) == GlyphId(0))
+        .collect()
+}
+
+/// Like CSS, `size` is the size of the em box in pixels. `PxScale` is instead the height from
+/// ascent to descent.
+fn px_scale<F: Font>(font: &F, size: f32) -> Result<PxScale, Error> {
+    let units_per_em = font.units_per_em().ok_or(Error::NoUnitsPerEm)?;
+    Ok(PxScale::from(size * font.height_unscaled() / units_per_em))
+}
+
+#[cfg(test)]
+mod tests {
+    use ab_glyph::FontRef;
+
+    use super::*;
+    use crate::index::BASELINE;
+
+    /* an "o" sitting on the baseline and a "p" reaching 250 units below it, 1000 units per em */
+    const TTF: &[u8] = include_bytes!("../test.ttf");
+
+    #[test]
+    fn test_render_text() {
+        let font = FontRef::try_from_slice(TTF).unwrap();
+        let options = RenderOptions {
+            size: 20.,
+            ..RenderOptions::default()
+        };
+        let loaded = render_text(&font, "op\no", &options).unwrap();
+        assert_eq!(loaded.rows.len(), 2);
+        let [o, p] = &loaded.rows[0][..] else {
+            panic!("not two glyphs in the first row");
+        };
+        /* 10px above the baseline and the p's 5px below it */
+        assert_eq!(o.image.height(), 15);
+        assert_eq!(p.image.height(), 15);
+        assert_eq!((o.glyph.descent, p.glyph.descent), (0, 6));
+        assert_eq!(missing_chars(&font, "op q"), ['q']);
+
+        /* each glyph is drawn on its own baseline, the o's at the bottom of its image */
+        let inked = |image: &RgbaImage| {
+            let rows = image.rows().map(|mut row| row.any(|pixel| pixel[3] > 0));
+            rows.collect::<Vec<_>>()
+        };
+        assert_eq!(inked(&o.image), [vec![false; 5], vec![true; 10]].concat());
+        assert_eq!(inked(&p.image), [true; 15]);
+
+        /* and the p's marker beside the last row above its baseline, 10px down */
+        let layout = loaded.flow_layout((0, 0)).unwrap();
+        let atlas = loaded.draw(&layout);
+        let at = layout.positions[0][1];
+        let marker = (0..p.image.height())
+            .filter(|&y| *atlas.get_pixel(at.x + p.image.width(), at.y + y) == BASELINE)
+            .collect::<Vec<_>>();
+        assert_eq!(marker, [9]);
+        let unpacked = crate::detect(&atlas).to_index();
+        assert_eq!(unpacked.rows[0][1].descent, p.glyph.descent);
+    }
+}
@@ -0,0 +1,318 @@
+//! Progress, warnings, and failures, printed for people or as JSON lines for other programs.
+
+use std::cell::Cell;
+use std::fmt;
+use std::io;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use atlast::check::{Problem, Severity};
+use atlast::image::ImageError;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    /// one JSON object per line on stderr, see `Event`
+    Json,
+}
+
+impl MessageFormat {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "human" => Some(MessageFormat::Human),
+            "json" => Some(MessageFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+static FORMAT: OnceLock<MessageFormat> = OnceLock::new();
+
+/// Set once from the command line, before anything is reported.
+pub fn set_format(format: MessageFormat) {
+    let _ = FORMAT.set(format);
+}
+
+fn format() -> MessageFormat {
+    FORMAT.get().copied().unwrap_or_default()
+}
+
+/// What went wrong, which decides the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Class {
+    /// anything without a class of its own
+    Other,
+    /// also a bad atlast.toml
+    Args,
+    /// a file couldn't be read or written
+    Io,
+    /// an index.html, index.json, or index.toml couldn't be parsed
+    Manifest,
+    /// a .tga or glyph image couldn't be decoded
+    Image,
+    /// glyphs don't fit in the atlas
+    Layout,
+    /// check, check-xml, or compose found errors, or merge left conflicts
+    Invalid,
+}
+
+impl Class {
+    pub fn code(self) -> i32 {
+        match self {
+            Class::Other => 1,
+            /* what clap exits with */
+            Class::Args => 2,
+            Class::Io => 3,
+            Class::Manifest => 4,
+            Class::Image => 5,
+            Class::Layout => 6,
+            Class::Invalid => 7,
+        }
+    }
+
+    /// The class of the outermost error in the chain that has one.
+    pub fn of(err: &anyhow::Error) -> Self {
+        err.chain().find_map(Self::of_cause).unwrap_or(Class::Other)
+    }
+
+    fn of_cause(cause: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        if let Some(failed) = cause.downcast_ref::<Failed>() {
+            return Some(failed.class);
+        }
+        if let Some(err) = cause.downcast_ref::<atlast::Error>() {
+            return Some(match err {
+                atlast::Error::Image(err) | atlast::Error::ImageFile { source: err, .. } => {
+                    Self::of_image(err)
+                }
+                atlast::Error::Manifest { .. } => Class::Manifest,
+                atlast::Error::Io { .. }
+                | atlast::Error::NoManifest { .. }
+                | atlast::Error::MissingImage { .. } => Class::Io,
+                atlast::Error::Size { .. }
+                | atlast::Error::TooWide { .. }
+                | atlast::Error::Clipped(_)
+                | atlast::Error::WrappedTooTall { .. }
+                | atlast::Error::AtlasTooSmall { .. } => Class::Layout,
+                /* like a --glyph or selector that isn't in the atlas */
+                atlast::Error::NoRow { .. }
+                | atlast::Error::NoGlyph { .. }
+                | atlast::Error::SelectorRows { .. }
+                | atlast::Error::SelectorGlyphs { .. }
+                | atlast::Error::WrapWithoutWidth
+                | atlast::Error::WrapKeptLayout => Class::Args,
+                /* like the mod's own XML */
+                _ => Class::Other,
+            });
+        }
+        /* patching an index.html gives one without a path */
+        if cause.is::<atlast::ManifestError>() {
+            return Some(Class::Manifest);
+        }
+        if let Some(err) = cause.downcast_ref::<ImageError>() {
+            return Some(Self::of_image(err));
+        }
+        if cause.is::<io::Error>() {
+            return Some(Class::Io);
+        }
+        None
+    }
+
+    fn of_image(err: &ImageError) -> Self {
+        match err {
+            /* a truncated image fails to read like a file would */
+            ImageError::IoError(err) => match err.kind() {
+                io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData => Class::Image,
+                _ => Class::Io,
+            },
+            _ => Class::Image,
+        }
+    }
+}
+
+/// A failure found by atlast itself, with no error type to tell what class it is.
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct Failed {
+    pub class: Class,
+    pub message: String,
+}
+
+pub fn failed(class: Class, message: impl Into<String>) -> anyhow::Error {
+    Failed {
+        class,
+        message: message.into(),
+    }
+    .into()
+}
+
+/// A line of `--message-format json`.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+enum Event<'a> {
+    Progress {
+        /// seconds since the last progress event of the same step
+        #[serde(skip_serializing_if = "Option::is_none")]
+        elapsed: Option<f32>,
+        message: &'a str,
+    },
+    Warning {
+        message: &'a str,
+    },
+    /// something wrong that doesn't stop atlast by itself, like each glyph that doesn't fit
+    Error {
+        message: &'a str,
+    },
+    /// the last line when atlast fails
+    Failed {
+        class: Class,
+        code: i32,
+        message: &'a str,
+    },
+}
+
+impl Event<'_> {
+    fn emit(&self) {
+        if let Ok(line) = serde_json::to_string(self) {
+            eprintln!("{line}");
+        }
+    }
+}
+
+/// Like `eprintln!`, but the message is prefixed with the time since the last one from `ts`.
+macro_rules! progress {
+    ($ts:expr, $($arg:tt)*) => {
+        $crate::report::emit_progress(Some(&$ts), format_args!($($arg)*))
+    };
+}
+pub(crate) use progress;
+
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::report::emit_warning(format_args!($($arg)*))
+    };
+}
+pub(crate) use warning;
+
+pub fn emit_progress(ts: Option<&TimeSince>, message: fmt::Arguments) {
+    match (format(), ts) {
+        (MessageFormat::Human, Some(ts)) => eprintln!("{ts} {message}"),
+        (MessageFormat::Human, None) => eprintln!("{message}"),
+        (MessageFormat::Json, ts) => Event::Progress {
+            elapsed: ts.map(TimeSince::lap),
+            message: &message.to_string(),
+        }
+        .emit(),
+    }
+}
+
+pub fn emit_warning(message: fmt::Arguments) {
+    match format() {
+        MessageFormat::Human => eprintln!("warning: {message}"),
+        MessageFormat::Json => Event::Warning {
+            message: &message.to_string(),
+        }
+        .emit(),
+    }
+}
+
+/// A problem found by `check`, on stderr.
+pub fn problem(problem: &Problem) {
+    match (format(), problem.severity) {
+        (MessageFormat::Human, _) => eprintln!("{problem}"),
+        (MessageFormat::Json, Severity::Warning) => Event::Warning {
+            message: &problem.message,
+        }
+        .emit(),
+        (MessageFormat::Json, Severity::Error) => Event::Error {
+            message: &problem.message,
+        }
+        .emit(),
+    }
+}
+
+/// Report why atlast failed and return the exit code for it.
+pub fn failure(err: &anyhow::Error) -> i32 {
+    let class = Class::of(err);
+    match format() {
+        MessageFormat::Human => {
+            eprintln!("error: {err:#}");
+        }
+        MessageFormat::Json => Event::Failed {
+            class,
+            code: class.code(),
+            message: &format!("{err:#}"),
+        }
+        .emit(),
+    }
+    class.code()
+}
+
+pub struct TimeSince(Cell<Option<Instant>>);
+
+impl Default for TimeSince {
+    fn default() -> Self {
+        TimeSince(Cell::new(None))
+    }
+}
+
+impl TimeSince {
+    /// Seconds since the last call, or zero the first time.
+    pub fn lap(&self) -> f32 {
+        let now = Instant::now();
+        self.0
+            .replace(Some(now))
+            .map(|then| now.saturating_duration_since(then).as_secs_f32())
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for TimeSince {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:.03}s]", self.lap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_class() {
+        let io = Err::<(), _>(io::Error::from(io::ErrorKind::NotFound)).context("read GameFont");
+        assert_eq!(Class::of(&io.unwrap_err()), Class::Io);
+        let image = atlast::read_tga(b"not a tga").map_err(anyhow::Error::from);
+        assert_eq!(Class::of(&image.unwrap_err()), Class::Image);
+        let manifest = atlast::read_manifest("rows = 7", atlast::IndexFormat::Toml)
+            .map_err(anyhow::Error::from)
+            .context("read GameFont");
+        assert_eq!(Class::of(&manifest.unwrap_err()), Class::Manifest);
+        let html = r#"<div data-atlas="row"><img src="a.png" data-at="7"></div>"#;
+        let patch = atlast::Index::from_html(r#"<div data-atlas="row"><img src="a.png"></div>"#)
+            .unwrap()
+            .patch_html(html)
+            .map_err(anyhow::Error::from)
+            .context("patch index.html");
+        assert_eq!(Class::of(&patch.unwrap_err()), Class::Manifest);
+        let glyph = Err::<(), _>(atlast::Error::NoRow { row: 9, rows: 3 }).context("edit");
+        assert_eq!(Class::of(&glyph.unwrap_err()), Class::Args);
+        let layout = Err::<(), _>(failed(Class::Layout, "too tall")).context("pack GameFont");
+        assert_eq!(Class::of(&layout.unwrap_err()), Class::Layout);
+        let invalid = Err::<(), _>(failed(Class::Invalid, "1 conflicts")).context("merge");
+        assert_eq!(Class::of(&invalid.unwrap_err()), Class::Invalid);
+        assert_eq!(Class::of(&anyhow::anyhow!("conflicts")), Class::Other);
+
+        let line = serde_json::to_string(&Event::Failed {
+            class: Class::Manifest,
+            code: Class::Manifest.code(),
+            message: "parse index.toml",
+        })
+        .unwrap();
+        assert_eq!(
+            line,
+            r#"{"event":"failed","class":"manifest","code":4,"message":"parse index.toml"}"#
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use image::imageops::{self, FilterType};
+use image::{Rgba, Rgba32FImage, RgbaImage};
+
+use crate::index::{LoadedGlyph, LoadedIndex};
+
+/// The factor GameFont_75.tga is scaled by, relative to GameFont.tga.
+pub const SCALE_75: f32 = 0.75;
+
+/// How glyph images are resampled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// sharpest for downscaling, can ring a little around hard edges
+    #[default]
+    Lanczos,
+    /// averages the pixels each output pixel covers, soft but no ringing
+    Box,
+    /// for pixel art, no blending at all
+    Nearest,
+}
+
+impl Filter {
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "lanczos" => Some(Filter::Lanczos),
+            "box" => Some(Filter::Box),
+            "nearest" => Some(Filter::Nearest),
+            _ => None,
+        }
+    }
+}
+
+/// A copy of `loaded` with every glyph scaled by `factor`.
+pub fn scale_index(loaded: &LoadedIndex, factor: f32, filter: Filter) -> LoadedIndex {
+    let rows = loaded
+        .rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|glyph| scale_glyph(glyph, factor, filter))
+                .collect()
+        })
+        .collect();
+    LoadedIndex { rows }
+}
+
+/// Resample the glyph's image and move its baseline marker to match.
+pub fn scale_glyph(loaded: &LoadedGlyph, factor: f32, filter: Filter) -> LoadedGlyph {
+    let width = scale_dim(loaded.image.width(), factor);
+    let height = scale_dim(loaded.image.height(), factor);
+    let image = resize(&loaded.image, width, height, filter);
+
+    let mut glyph = loaded.glyph.clone();
+    glyph.descent = scale_descent(glyph.descent, factor).min(height);
+    /* where it was in the full size atlas means nothing in this one */
+    glyph.at = None;
+    LoadedGlyph { glyph, image }
+}
+
+/// Resize with premultiplied alpha. Otherwise the color of transparent pixels, which is often
+/// black or the frame's pink, bleeds into the edges of the glyph.
+pub fn resize(image: &RgbaImage, width: u32, height: u32, filter: Filter) -> RgbaImage {
+    let premultiplied = premultiply(image);
+    let resized = match filter {
+        Filter::Lanczos => imageops::resize(&premultiplied, width, height, FilterType::Lanczos3),
+        Filter::Nearest => imageops::resize(&premultiplied, width, height, FilterType::Nearest),
+        Filter::Box => box_resize(&premultiplied, width, height),
+    };
+    unpremultiply(&resized)
+}
+
+fn premultiply(image: &RgbaImage) -> Rgba32FImage {
+    Rgba32FImage::from_fn(image.width(), image.height(), |x, y| {
+        let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+        let a = a as f32 / 255.;
+        let c = |c: u8| c as f32 / 255. * a;
+        Rgba([c(r), c(g), c(b), a])
+    })
+}
+
+/// Lanczos can overshoot, so values are clamped. Fully transparent pixels are black so they
+/// can't be mistaken for frame pixels.
+fn unpremultiply(image: &Rgba32FImage) -> RgbaImage {
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+        let a = a.clamp(0., 1.);
+        let alpha = (a * 255.).round() as u8;
+        if alpha == 0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+        let c = |c: f32| ((c / a).clamp(0., 1.) * 255.).round() as u8;
+        Rgba([c(r), c(g), c(b), alpha])
+    })
+}
+
+/// Area averaging, each output pixel is the mean of the source pixels under it, weighted by how
+/// much of each is covered.
+fn box_resize(image: &Rgba32FImage, width: u32, height: u32) -> Rgba32FImage {
+    let horizontal = Rgba32FImage::from_fn(width, image.height(), |x, y| {
+        average(image.width(), width, x, |sx| *image.get_pixel(sx, y))
+    });
+    Rgba32FImage::from_fn(width, height, |x, y| {
+        average(image.height(), height, y, |sy| *horizontal.get_pixel(x, sy))
+    })
+}
+
+fn average(src_len: u32, dst_len: u32, i: u32, pixel: impl Fn(u32) -> Rgba<f32>) -> Rgba<f32> {
+    let step = src_len as f32 / dst_len as f32;
+    let (start, end) = (i as f32 * step, (i + 1) as f32 * step);
+    let mut sum = [0f32; 4];
+    let mut total = 0.;
+    for s in start.floor() as u32..(end.ceil() as u32).min(src_len) {
+        let weight = (end.min(s as f32 + 1.) - start.max(s as f32)).max(0.);
+        let Rgba(p) = pixel(s);
+        sum.iter_mut()
+            .zip(p)
+            .for_each(|(sum, p)| *sum += p * weight);
+        total += weight;
+    }
+    Rgba(sum.map(|sum| if total > 0. { sum / total } else { 0. }))
+}
+
+fn scale_dim(dim: u32, factor: f32) -> u32 {
+    ((dim as f32 * factor).round() as u32).max(1)
+}
+
+/// A descent of one puts the baseline under the bottom row of the glyph, so only the rows below
+/// that are scaled. Zero stays zero since it means there's no marker.
+pub fn scale_descent(descent: u32, factor: f32) -> u32 {
+    match descent {
+        0 => 0,
+        d => ((d - 1) as f32 * factor).round() as u32 + 1,
+    }
+}
+
+/// `size` scaled by `factor`, rounding up so the scaled atlas always fits.
+pub fn scale_size((width, height): (u32, u32), factor: f32) -> (u32, u32) {
+    let scale = |dim: u32| (dim as f32 * factor).ceil() as u32;
+    (scale(width), scale(height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::IndexGlyph;
+
+    #[test]
+    fn test_scale_glyph() {
+        let loaded = LoadedGlyph {
+            glyph: IndexGlyph {
+                path: "000.png".into(),
+                descent: 5,
+                ..IndexGlyph::default()
+            },
+            image: RgbaImage::new(10, 21),
+        };
+        let scaled = scale_glyph(&loaded, SCALE_75, Filter::default());
+        assert_eq!(scaled.image.dimensions(), (8, 16));
+        assert_eq!(scaled.glyph.descent, 4);
+        assert_eq!(scale_descent(0, SCALE_75), 0);
+        assert_eq!(scale_descent(1, SCALE_75), 1);
+        assert_eq!(scale_size((2046, 540), SCALE_75), (1535, 405));
+    }
+
+    #[test]
+    fn test_resize_has_no_fringe() {
+        /* white on the left, transparent pink on the right */
+        let image = RgbaImage::from_fn(4, 1, |x, _| match x {
+            0 | 1 => Rgba([255, 255, 255, 255]),
+            _ => Rgba([255, 0, 255, 0]),
+        });
+        for filter in [Filter::Lanczos, Filter::Box, Filter::Nearest] {
+            let resized = resize(&image, 3, 1, filter);
+            for &Rgba([r, g, b, a]) in resized.pixels() {
+                match a {
+                    0 => assert_eq!([r, g, b], [0, 0, 0], "{filter:?}"),
+                    _ => assert_eq!([r, g, b], [255, 255, 255], "{filter:?}"),
+                }
+            }
+        }
+        assert_eq!(
+            resize(&image, 2, 1, Filter::Box).get_pixel(0, 0),
+            &Rgba([255, 255, 255, 255])
+        );
+    }
+}
@@ -0,0 +1,398 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use image::{ImageFormat, RgbaImage};
+use notify::Event;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use atlast::check::Problem;
+use atlast::{edit, preview};
+
+use crate::report::{self, progress, warning, TimeSince};
+use crate::watch::{self, Watched};
+use crate::{cli, Packing};
+
+/// How long `changes` waits for one before answering anyway.
+const POLL: Duration = Duration::from_secs(25);
+
+/// The most `replace` reads of an image dropped on the page.
+const MAX_UPLOAD: u64 = 64 << 20;
+
+/// What the server shows, and a version that goes up whenever that changes.
+struct State {
+    watched: Watched,
+    version: u64,
+    /// why the directory couldn't be read again
+    error: Option<String>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+    port: u16,
+    /// in the page, and needed to post changes
+    token: String,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Serve the listing and the atlas packed from `dir` on localhost, reloading the page whenever
+/// something in `dir` changes, until the watcher fails.
+pub fn serve(dir: &str, port: u16, options: &Packing) -> Result<()> {
+    let ts = TimeSince::default();
+    options.pack.check()?;
+
+    let (_watcher, events, root) = watch::watch_dir(dir)?;
+    let watched = Watched::read(dir)?;
+    progress!(ts, "read {} images from {dir}", watched.images.len());
+
+    let server = Server::http(("127.0.0.1", port))
+        .map_err(anyhow::Error::from_boxed)
+        .with_context(|| format!("listen on port {port}"))?;
+    let shared = Shared {
+        state: Mutex::new(State {
+            watched,
+            version: 0,
+            error: None,
+        }),
+        changed: Condvar::new(),
+        port,
+        token: token(),
+    };
+    progress!(
+        ts,
+        "serving {dir} at http://127.0.0.1:{port}/, ctrl-c to stop"
+    );
+
+    thread::scope(|scope| {
+        let (server, shared) = (&server, &shared);
+        scope.spawn(move || {
+            for request in server.incoming_requests() {
+                scope.spawn(move || respond(request, shared, options));
+            }
+        });
+        let watching = reload_on_change(&ts, dir, &events, &root, shared);
+        server.unblock();
+        watching
+    })
+}
+
+fn reload_on_change(
+    ts: &TimeSince,
+    dir: &str,
+    events: &Receiver<notify::Result<Event>>,
+    root: &Path,
+    shared: &Shared,
+) -> Result<()> {
+    loop {
+        let changed = watch::wait_for_changes(events, root)?;
+        let mut state = shared.state();
+        match state.watched.reload(dir, &changed) {
+            Ok(changes) if changes.is_empty() => continue,
+            Ok(changes) => {
+                progress!(ts, "{changes}, reloading");
+                state.error = None;
+            }
+            Err(err) => {
+                report::problem(&Problem::error(format!("{err:#}")));
+                state.error = Some(format!("{err:#}"));
+            }
+        }
+        state.version += 1;
+        shared.changed.notify_all();
+    }
+}
+
+type Reply = Response<Cursor<Vec<u8>>>;
+
+fn respond(mut request: Request, shared: &Shared, options: &Packing) {
+    let url = request.url().to_owned();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let post = *request.method() == Method::Post;
+    let sender = check_sender(request.headers(), post, shared.port, &shared.token);
+    let reply = match (request.method(), path) {
+        _ if let Err(why) = sender => reply(403, "text/plain", why.as_bytes().to_vec()),
+        (Method::Get, "/" | "/index.html") => {
+            let state = shared.state();
+            let page = preview::page(
+                &state.watched.index,
+                state.version,
+                state.error.as_deref(),
+                &shared.token,
+            );
+            reply(200, "text/html; charset=utf-8", page.into_bytes())
+        }
+        (Method::Get, "/atlas.png") => {
+            let frame = query.split('&').any(|param| param == "frame");
+            match atlas(shared, options, frame) {
+                Ok(png) => reply(200, "image/png", png),
+                Err(err) => reply(500, "text/plain", format!("{err:#}").into_bytes()),
+            }
+        }
+        (Method::Get, "/changes") => {
+            let since = query
+                .strip_prefix("since=")
+                .and_then(|since| since.parse().ok())
+                .unwrap_or(0);
+            let (state, _) = shared
+                .changed
+                .wait_timeout_while(shared.state(), POLL, |state| state.version == since)
+                .unwrap_or_else(PoisonError::into_inner);
+            reply(200, "text/plain", state.version.to_string().into_bytes())
+        }
+        /* only images in the index, straight from memory */
+        (Method::Get, path) => {
+            let state = shared.state();
+            let image = glyph_path(path).and_then(|path| state.watched.images.get(&path));
+            match image.map(png) {
+                Some(Ok(png)) => reply(200, "image/png", png),
+                Some(Err(err)) => reply(500, "text/plain", format!("{err:#}").into_bytes()),
+                None => reply(404, "text/plain", b"not found".to_vec()),
+            }
+        }
+        (Method::Post, "/move") => edit(shared, |watched| {
+            let (from, to) = (slot(query, "from")?, slot(query, "to")?);
+            let mut index = watched.index.clone();
+            let path = edit::index_glyph_mut(&mut index, from)?.path.clone();
+            edit::move_glyph(&mut index, from, to)?;
+            watched.write_index(index)?;
+            Ok(format!("moved {path} to row {}, glyph {}", to.0, to.1))
+        }),
+        (Method::Post, "/descent") => edit(shared, |watched| {
+            let glyph = slot(query, "glyph")?;
+            let row = param(query, "row")?
+                .parse()
+                .context("expected a number for row")?;
+            let mut index = watched.index.clone();
+            let indexed = edit::index_glyph_mut(&mut index, glyph)?;
+            let height = watched
+                .images
+                .get(&indexed.path)
+                .map_or(0, RgbaImage::height);
+            anyhow::ensure!(
+                row < height,
+                "row {row} is past the bottom of {}, which is {height} tall",
+                indexed.path
+            );
+            let descent = preview::descent_at_row(height, row);
+            indexed.descent = descent;
+            let path = indexed.path.clone();
+            watched.write_index(index)?;
+            Ok(format!("set descent of {path} to {descent}"))
+        }),
+        (Method::Post, "/replace") => {
+            let mut bytes = Vec::new();
+            match request.as_reader().take(MAX_UPLOAD).read_to_end(&mut bytes) {
+                Ok(_) => edit(shared, |watched| {
+                    let glyph = slot(query, "glyph")?;
+                    let path = edit::index_glyph_mut(&mut watched.index, glyph)?
+                        .path
+                        .clone();
+                    let image = image::load_from_memory(&bytes)
+                        .context("read the dropped image")?
+                        .to_rgba8();
+                    let (width, height) = image.dimensions();
+                    watched.write_image(&path, image)?;
+                    Ok(format!("replaced {path} with a {width}x{height} image"))
+                }),
+                Err(err) => reply(
+                    400,
+                    "text/plain",
+                    format!("read upload: {err}").into_bytes(),
+                ),
+            }
+        }
+        _ => reply(405, "text/plain", b"not allowed".to_vec()),
+    };
+    if let Err(err) = request.respond(reply) {
+        warning!("answer {url}: {err}");
+    }
+}
+
+/// Only answer requests made to this server by name, so a page on another site can't reach it by
+/// pointing its own name at 127.0.0.1, and only take changes from the page with `token` in it.
+fn check_sender(
+    headers: &[Header],
+    post: bool,
+    port: u16,
+    token: &str,
+) -> Result<(), &'static str> {
+    let header = |name: &'static str| {
+        headers
+            .iter()
+            .find(|header| header.field.equiv(name))
+            .map(|header| header.value.as_str())
+    };
+    let hosts = [format!("127.0.0.1:{port}"), format!("localhost:{port}")];
+    if !header("Host").is_some_and(|host| hosts.iter().any(|ours| host == ours)) {
+        return Err("not served to other hosts");
+    }
+    let from_us = |origin: &str| hosts.iter().any(|ours| origin == format!("http://{ours}"));
+    if !header("Origin").is_none_or(from_us) {
+        return Err("not served to other sites");
+    }
+    if post && header("X-Atlast-Token") != Some(token) {
+        return Err("changes are only taken from the page, reload it");
+    }
+    Ok(())
+}
+
+/// Random enough that another page can't guess it.
+fn token() -> String {
+    let half = || RandomState::new().build_hasher().finish();
+    format!("{:016x}{:016x}", half(), half())
+}
+
+/// Make a change from the page and write it to the directory. The watcher sees the write too,
+/// but the page is reloaded from here so it doesn't wait on that.
+fn edit(shared: &Shared, change: impl FnOnce(&mut Watched) -> Result<String>) -> Reply {
+    let mut state = shared.state();
+    match change(&mut state.watched) {
+        Ok(done) => {
+            progress!(TimeSince::default(), "{done}");
+            state.error = None;
+            state.version += 1;
+            shared.changed.notify_all();
+            reply(200, "text/plain", done.into_bytes())
+        }
+        Err(err) => reply(400, "text/plain", format!("{err:#}").into_bytes()),
+    }
+}
+
+/// The value of `name` in a query like `from=0,1&to=2,0`.
+fn param<'q>(query: &'q str, name: &str) -> Result<&'q str> {
+    query
+        .split('&')
+        .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+        .with_context(|| format!("expected {name}= in the query"))
+}
+
+/// A `ROW,GLYPH` parameter, which the page sends as `ROW,GLYPH` or `ROW%2CGLYPH`.
+fn slot(query: &str, name: &str) -> Result<(usize, usize)> {
+    let value = param(query, name)?.replace("%2C", ",");
+    cli::parse_slot(&value).with_context(|| format!("expected {name}=ROW,GLYPH, found: {value}"))
+}
+
+/// Packed like `pack` would, but with clipped glyphs cut off instead of failing.
+fn atlas(shared: &Shared, options: &Packing, frame: bool) -> Result<Vec<u8>> {
+    let (loaded, size) = {
+        let state = shared.state();
+        (state.watched.loaded()?, state.watched.index.size)
+    };
+    let ts = TimeSince::default();
+    let (_, atlas) = crate::pack_loaded(&ts, "atlas.png", loaded, size, options)?
+        .into_iter()
+        .next()
+        .context("nothing packed")?;
+    match frame {
+        true => png(&preview::show_frame(&atlas)),
+        false => png(&atlas),
+    }
+}
+
+fn png(image: &RgbaImage) -> Result<Vec<u8>> {
+    let mut bytes = Cursor::new(Vec::new());
+    image.write_to(&mut bytes, ImageFormat::Png)?;
+    Ok(bytes.into_inner())
+}
+
+fn reply(status: u16, content_type: &str, body: Vec<u8>) -> Reply {
+    let header = |name: &str, value: &str| Header::from_bytes(name, value).ok();
+    [
+        header("Content-Type", content_type),
+        /* everything changes as files are saved */
+        header("Cache-Control", "no-store"),
+    ]
+    .into_iter()
+    .flatten()
+    .fold(
+        Response::from_data(body).with_status_code(status),
+        Response::with_header,
+    )
+}
+
+/// `/icons/gold%20coin.png` is `icons/gold coin.png`, like it's written in the index.
+fn glyph_path(url_path: &str) -> Option<String> {
+    let bytes = url_path.strip_prefix('/')?.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glyph_path() {
+        assert_eq!(
+            glyph_path("/icons/gold%20coin.png").as_deref(),
+            Some("icons/gold coin.png")
+        );
+        assert_eq!(glyph_path("/%C3%A9.png").as_deref(), Some("é.png"));
+        assert_eq!(glyph_path("/bad%2"), None);
+        assert_eq!(glyph_path("gold.png"), None);
+    }
+
+    #[test]
+    fn test_check_sender() {
+        let headers = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| Header::from_bytes(*name, *value).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let page = headers(&[("Host", "127.0.0.1:8000"), ("X-Atlast-Token", "ab")]);
+        assert_eq!(check_sender(&page, true, 8000, "ab"), Ok(()));
+        let page = headers(&[
+            ("Host", "localhost:8000"),
+            ("Origin", "http://localhost:8000"),
+        ]);
+        assert_eq!(check_sender(&page, false, 8000, "ab"), Ok(()));
+
+        let rebound = headers(&[("Host", "evil.example:8000"), ("X-Atlast-Token", "ab")]);
+        assert!(check_sender(&rebound, false, 8000, "ab").is_err());
+        let other_site = headers(&[
+            ("Host", "127.0.0.1:8000"),
+            ("Origin", "https://evil.example"),
+            ("X-Atlast-Token", "ab"),
+        ]);
+        assert!(check_sender(&other_site, true, 8000, "ab").is_err());
+        let no_token = headers(&[("Host", "127.0.0.1:8000")]);
+        assert!(check_sender(&no_token, false, 8000, "ab").is_ok());
+        assert!(check_sender(&no_token, true, 8000, "ab").is_err());
+        assert!(check_sender(&no_token, false, 8001, "ab").is_err());
+    }
+
+    #[test]
+    fn test_slot() {
+        assert_eq!(slot("from=0,1&to=2%2C0", "from").unwrap(), (0, 1));
+        assert_eq!(slot("from=0,1&to=2%2C0", "to").unwrap(), (2, 0));
+        assert!(slot("from=0,1", "to").is_err());
+        assert!(slot("to=x", "to").is_err());
+    }
+}
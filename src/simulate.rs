@@ -0,0 +1,260 @@
+//! Laying out game text with the glyphs of an atlas, to see spacing and baselines without
+//! starting the game.
+
+use std::collections::BTreeMap;
+
+use image::{Rgba, RgbaImage};
+
+use crate::index::{Index, LoadedGlyph, LoadedIndex};
+use crate::layout::{InfoCounts, BTS_COUNTS, BTS_ICON_ROWS, BTS_IDS};
+use crate::Error;
+
+/// How `simulate` draws text.
+#[derive(Debug, Clone, Copy)]
+pub struct SimulateOptions {
+    /// what the game numbers icons after, for finding `[ICON_...]`
+    pub counts: InfoCounts,
+    /// how far a space moves along, since the atlas has no glyph for it
+    pub space: u32,
+    pub background: Rgba<u8>,
+    /// color the row each line of text sits on, behind the glyphs
+    pub guide: Option<Rgba<u8>>,
+}
+
+impl Default for SimulateOptions {
+    fn default() -> Self {
+        SimulateOptions {
+            counts: BTS_COUNTS,
+            space: 4,
+            background: Rgba([0, 0, 0, 255]),
+            guide: None,
+        }
+    }
+}
+
+/// Text drawn by `simulate`.
+#[derive(Debug)]
+pub struct Simulated {
+    pub image: RgbaImage,
+    pub lines: usize,
+    /// characters and tags that weren't drawn, like `Ω` or `[ICON_TOFU]`, each once
+    pub missing: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Item<'a> {
+    Glyph(&'a LoadedGlyph),
+    Space,
+}
+
+/// Draw `text` with the glyphs in `loaded` like the game would: characters and `[ICON_...]` tags
+/// are looked up by the ID the game gives each glyph, glyphs sit on a shared baseline, and each
+/// moves along by its width. `[NEWLINE]` and `\n` start a new line. Other tags, like
+/// `[COLOR_RED]`, aren't drawn.
+///
+/// The cyan pixel is beside the lowest row that sits on the baseline, so a glyph with a descent
+/// of 3 hangs two rows below it. A glyph without one sits on its bottom row.
+pub fn simulate(
+    loaded: &LoadedIndex,
+    text: &str,
+    options: &SimulateOptions,
+) -> Result<Simulated, Error> {
+    let glyphs = glyphs_by_id(loaded);
+    let mut missing = Vec::new();
+    let lines = split_lines(text, &glyphs, &symbol_ids(&options.counts), &mut missing);
+
+    /* every line is as tall as the tallest, like the game's fixed line height */
+    let (mut above, mut below) = (0, 0);
+    for item in lines.iter().flatten() {
+        let (_, h, d) = size(item, options);
+        above = above.max(h - d);
+        below = below.max(d);
+    }
+    let width = lines
+        .iter()
+        .map(|line| line.iter().map(|item| size(item, options).0).sum::<u32>())
+        .max()
+        .unwrap_or(0);
+    let height = (above + below) * lines.len() as u32;
+    if width == 0 || height == 0 {
+        return Err(Error::NothingToDraw {
+            text: text.to_owned(),
+        });
+    }
+
+    let mut image = RgbaImage::from_pixel(width, height, options.background);
+    for (i, line) in lines.iter().enumerate() {
+        let baseline = (above + below) * i as u32 + above;
+        if let Some(guide) = options.guide.filter(|_| above > 0) {
+            for x in 0..width {
+                image.put_pixel(x, baseline - 1, guide);
+            }
+        }
+        let mut x = 0;
+        for item in line {
+            let (w, h, d) = size(item, options);
+            if let Item::Glyph(glyph) = item {
+                let y = baseline + d - h;
+                image::imageops::overlay(&mut image, &glyph.image, x.into(), y.into());
+            }
+            x += w;
+        }
+    }
+
+    Ok(Simulated {
+        image,
+        lines: lines.len(),
+        missing,
+    })
+}
+
+/// The glyphs and spaces of each line of `text`, adding what isn't in `glyphs` to `missing`.
+fn split_lines<'a>(
+    text: &str,
+    glyphs: &BTreeMap<u32, &'a LoadedGlyph>,
+    symbols: &BTreeMap<&str, u32>,
+    missing: &mut Vec<String>,
+) -> Vec<Vec<Item<'a>>> {
+    let mut lines = vec![vec![]];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (id, what) = if c != '[' || !rest.contains(']') {
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '\n' => {
+                    lines.push(vec![]);
+                    continue;
+                }
+                '\r' => continue,
+                ' ' => {
+                    lines
+                        .last_mut()
+                        .expect("starts with a line")
+                        .push(Item::Space);
+                    continue;
+                }
+                c => (Some(c as u32), c.to_string()),
+            }
+        } else {
+            let (tag, after) = rest[1..].split_once(']').expect("checked for ]");
+            rest = after;
+            match tag {
+                "NEWLINE" => {
+                    lines.push(vec![]);
+                    continue;
+                }
+                _ if tag.starts_with("ICON_") => (symbols.get(tag).copied(), format!("[{tag}]")),
+                _ => continue,
+            }
+        };
+        match id.and_then(|id| glyphs.get(&id)) {
+            Some(glyph) => lines
+                .last_mut()
+                .expect("starts with a line")
+                .push(Item::Glyph(glyph)),
+            None if missing.contains(&what) => (),
+            None => missing.push(what),
+        }
+    }
+    lines
+}
+
+/// The width, height, and rows below the baseline of a glyph or space.
+fn size(item: &Item<'_>, options: &SimulateOptions) -> (u32, u32, u32) {
+    match item {
+        Item::Glyph(glyph) => {
+            let (width, height) = glyph.image.dimensions();
+            let below = glyph.glyph.descent.saturating_sub(1).min(height);
+            (width, height, below)
+        }
+        Item::Space => (options.space, 0, 0),
+    }
+}
+
+fn glyphs_by_id(loaded: &LoadedIndex) -> BTreeMap<u32, &LoadedGlyph> {
+    /* only the number of glyphs in each row matters for the IDs */
+    let index = Index {
+        rows: loaded
+            .rows
+            .iter()
+            .map(|row| row.iter().map(|loaded| loaded.glyph.clone()).collect())
+            .collect(),
+        size: None,
+    };
+    let ids = BTS_IDS.assign(&index);
+    loaded
+        .rows
+        .iter()
+        .flatten()
+        .zip(ids.iter().flatten())
+        .filter_map(|(glyph, id)| Some((id.id()?, glyph)))
+        .collect()
+}
+
+/// The ID the game gives `[ICON_GOLD]` and the others, which doesn't depend on the atlas.
+fn symbol_ids(counts: &InfoCounts) -> BTreeMap<&'static str, u32> {
+    let starts = BTS_IDS.expected_row_starts(counts);
+    BTS_ICON_ROWS
+        .iter()
+        .zip(starts)
+        .flat_map(|(row, start)| {
+            row.glyphs
+                .iter()
+                .zip(start..)
+                .filter_map(|(glyph, id)| Some((glyph.symbol?, id)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::IndexGlyph;
+    use crate::layout::text_row_lengths;
+
+    #[test]
+    fn test_simulate() {
+        /* every glyph is 2 wide and 5 tall, text is white and icons are red */
+        let glyph = |descent, shade: Rgba<u8>| LoadedGlyph {
+            glyph: IndexGlyph {
+                descent,
+                ..IndexGlyph::default()
+            },
+            image: RgbaImage::from_pixel(2, 5, shade),
+        };
+        let (white, red) = (Rgba([255; 4]), Rgba([255, 0, 0, 255]));
+        let mut rows = text_row_lengths()
+            .into_iter()
+            .map(|len| vec![glyph(0, white); len])
+            .collect::<Vec<_>>();
+        rows.extend(
+            BTS_ICON_ROWS
+                .iter()
+                .map(|row| vec![glyph(0, red); row.glyphs.len()]),
+        );
+        /* g hangs two rows below the baseline */
+        rows[1][15].glyph.descent = 3;
+        let loaded = LoadedIndex { rows };
+
+        let options = SimulateOptions::default();
+        let simulated =
+            simulate(&loaded, "a g[ICON_GOLD][COLOR_RED]Ω[NEWLINE]Ω", &options).unwrap();
+        assert_eq!(simulated.missing, ["Ω"]);
+        assert_eq!(simulated.lines, 2);
+        /* a, space, g, gold */
+        assert_eq!(simulated.image.dimensions(), (2 + 4 + 2 + 2, 2 * 7));
+        let column = |x| {
+            (0..7)
+                .map(|y| simulated.image.get_pixel(x, y)[1])
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(column(0), [255, 255, 255, 255, 255, 0, 0]);
+        assert_eq!(column(2), [0; 7]);
+        assert_eq!(column(6), [0, 0, 255, 255, 255, 255, 255]);
+        assert_eq!(*simulated.image.get_pixel(8, 4), red);
+
+        let simulated = simulate(&loaded, "[ICON_GOLD]", &options).unwrap();
+        assert!(simulate(&loaded, "[COLOR_RED]", &options).is_err());
+        assert_eq!(simulated.image.dimensions(), (2, 5));
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use atlast::check::Problem;
+use atlast::index::{Index, LoadedIndex};
+use atlast::manifest::IndexFormat;
+
+use crate::project::Project;
+use crate::report::{self, progress, warning, TimeSince};
+use crate::Packing;
+
+/// How long to wait for more changes after one, since editors save in a few steps.
+const SETTLE: Duration = Duration::from_millis(100);
+
+/// `pack`, then again whenever a glyph image or the manifest in `input` changes, reading only
+/// what changed. Failures after the first pack are reported and waited out.
+pub fn pack_on_change(
+    destination: &str,
+    input: &str,
+    dry_run: bool,
+    options: &Packing,
+) -> Result<()> {
+    let ts = TimeSince::default();
+    options.pack.check()?;
+
+    let (_watcher, events, root) = watch_dir(input)?;
+
+    progress!(ts, "packing images under {input} to {destination}");
+    let mut watched = Watched::read(input)?;
+    progress!(
+        ts,
+        "read {} and {} images",
+        watched.format.path_in(input).display(),
+        watched.images.len()
+    );
+    let mut check_xml = true;
+
+    loop {
+        if let Err(err) = repack(&ts, destination, &watched, dry_run, options, check_xml) {
+            report::problem(&Problem::error(format!("{err:#}")));
+        }
+        progress!(ts, "watching {input} for changes...");
+
+        let changes = loop {
+            let changed = wait_for_changes(&events, &root)?;
+            match watched.reload(input, &changed) {
+                Ok(changes) if changes.is_empty() => continue,
+                Ok(changes) => break changes,
+                Err(err) => report::problem(&Problem::error(format!("{err:#}"))),
+            }
+        };
+        progress!(ts, "{changes}, repacking");
+        check_xml = changes.manifest.is_some();
+    }
+}
+
+fn repack(
+    ts: &TimeSince,
+    destination: &str,
+    watched: &Watched,
+    dry_run: bool,
+    options: &Packing,
+    check_xml: bool,
+) -> Result<()> {
+    if let Some(assets) = options.check_xml.filter(|_| check_xml) {
+        crate::check_xml(ts, &watched.dir, &watched.index, assets)?;
+    }
+    let loaded = watched.loaded()?;
+    let atlases = crate::pack_loaded(ts, destination, loaded, watched.index.size, options)?;
+
+    if dry_run {
+        progress!(ts, "dry run, not writing {destination}");
+        return Ok(());
+    }
+    for (destination, atlas) in atlases {
+        crate::save_atomically(&atlas, &destination)?;
+        progress!(ts, "written to {destination}");
+    }
+    Ok(())
+}
+
+/// Start watching everything under `input`, returning the watcher to keep until done, the events
+/// for `wait_for_changes`, and the full path of `input`.
+pub fn watch_dir(
+    input: &str,
+) -> Result<(RecommendedWatcher, Receiver<notify::Result<Event>>, PathBuf)> {
+    let root = fs::canonicalize(input).with_context(|| format!("open {input}"))?;
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).context("watch for changes")?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .with_context(|| format!("watch {input}"))?;
+    Ok((watcher, events, root))
+}
+
+/// Block until something under `root` changes and then until it stops changing, returning the
+/// paths relative to `root` like they're written in an index.
+pub fn wait_for_changes(
+    events: &Receiver<notify::Result<Event>>,
+    root: &Path,
+) -> Result<BTreeSet<String>> {
+    let mut changed = BTreeSet::new();
+    loop {
+        let event = match changed.is_empty() {
+            true => events.recv().ok(),
+            false => match events.recv_timeout(SETTLE) {
+                Ok(event) => Some(event),
+                Err(RecvTimeoutError::Timeout) => return Ok(changed),
+                Err(RecvTimeoutError::Disconnected) => None,
+            },
+        };
+        let event = event.context("stopped watching for changes")?;
+        match event {
+            Ok(event) if matches!(event.kind, EventKind::Access(_)) => (),
+            Ok(event) => changed.extend(
+                event
+                    .paths
+                    .iter()
+                    .filter_map(|path| relative_path(path, root)),
+            ),
+            Err(err) => warning!("{err}"),
+        }
+    }
+}
+
+/// `root/icons/gold.png` is `icons/gold.png`.
+fn relative_path(path: &Path, root: &Path) -> Option<String> {
+    let parts = path
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .map(|part| part.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    Some(parts.join("/"))
+}
+
+/// The unpacked directory as last read, so only what changed needs to be read again.
+pub struct Watched {
+    dir: String,
+    format: IndexFormat,
+    pub index: Index,
+    /// by their path in the index
+    pub images: BTreeMap<String, RgbaImage>,
+}
+
+/// What `Watched::reload` read again.
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    pub manifest: Option<IndexFormat>,
+    pub changed: Vec<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Watched {
+    pub fn read(dir: &str) -> Result<Self> {
+        let (format, index) = crate::read_index_dir(dir)?;
+        let images = index
+            .clone()
+            .load_images(dir)?
+            .rows
+            .into_iter()
+            .flatten()
+            .map(|loaded| (loaded.glyph.path, loaded.image))
+            .collect();
+        Ok(Watched {
+            dir: dir.to_owned(),
+            format,
+            index,
+            images,
+        })
+    }
+
+    pub fn loaded(&self) -> Result<LoadedIndex> {
+        Ok(self
+            .index
+            .clone()
+            .with_images(|path| self.images.get(path).cloned())?)
+    }
+
+    /// Write `index` back to the directory, in the format it was read from.
+    pub fn write_index(&mut self, index: Index) -> Result<()> {
+        index.write_to_dir(&self.dir, self.format)?;
+        self.index = index;
+        Ok(())
+    }
+
+    /// Write `image` over the glyph image at `path`.
+    pub fn write_image(&mut self, path: &str, image: RgbaImage) -> Result<()> {
+        let file = Path::new(&self.dir).join(path);
+        crate::save_atomically(&image, &file.to_string_lossy())?;
+        self.images.insert(path.to_owned(), image);
+        Ok(())
+    }
+
+    /// Read the manifest again if it's in `changed`, and the images in `changed` or new to the
+    /// manifest. Nothing is kept if any of it fails to read.
+    pub fn reload(&mut self, dir: &str, changed: &BTreeSet<String>) -> Result<Changes> {
+        if changed.contains(Project::FILE_NAME) {
+            warning!(
+                "{} changed, stop and start --watch again to use it",
+                Project::FILE_NAME
+            );
+        }
+
+        let manifest_changed = IndexFormat::DETECT_ORDER
+            .into_iter()
+            .any(|format| changed.contains(format.file_name()));
+        let (format, index) = match manifest_changed {
+            true => crate::read_index_dir(dir)?,
+            false => (self.format, self.index.clone()),
+        };
+
+        let mut changes = Changes {
+            manifest: Some(format).filter(|_| index != self.index),
+            ..Changes::default()
+        };
+        let mut images = BTreeMap::new();
+        for glyph in index.rows.iter().flatten() {
+            let path = &glyph.path;
+            if images.contains_key(path) {
+                continue;
+            }
+            let old = self.images.get(path);
+            let image = match old {
+                Some(image) if !changed.contains(&path.replace('\\', "/")) => image.clone(),
+                _ => {
+                    let file = Path::new(dir).join(path);
+                    image::open(&file)
+                        .with_context(|| format!("open {}", file.display()))?
+                        .into_rgba8()
+                }
+            };
+            match old {
+                Some(old) if *old == image => (),
+                Some(_) => changes.changed.push(path.clone()),
+                None => changes.added.push(path.clone()),
+            }
+            images.insert(path.clone(), image);
+        }
+        changes.removed = (self.images.keys())
+            .filter(|path| !images.contains_key(*path))
+            .cloned()
+            .collect();
+
+        *self = Watched {
+            dir: dir.to_owned(),
+            format,
+            index,
+            images,
+        };
+        Ok(changes)
+    }
+}
+
+impl Changes {
+    /// Also true when files were saved without changing.
+    pub fn is_empty(&self) -> bool {
+        *self == Changes::default()
+    }
+}
+
+impl fmt::Display for Changes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(format) = self.manifest {
+            parts.push(format!("{} changed", format.file_name()));
+        }
+        for (what, paths) in [
+            ("changed", &self.changed),
+            ("added", &self.added),
+            ("removed", &self.removed),
+        ] {
+            match paths.len() {
+                0 => (),
+                1..=3 => parts.push(format!("{what} {}", paths.join(", "))),
+                n => parts.push(format!(
+                    "{what} {} and {} more",
+                    paths[..2].join(", "),
+                    n - 2
+                )),
+            }
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("atlast-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+        let save = |name: &str, color: u8| {
+            RgbaImage::from_pixel(2, 3, Rgba([color, 0, 0, 255]))
+                .save(dir.join(name))
+                .unwrap()
+        };
+        let manifest = |paths: &[&str]| {
+            let glyphs = paths
+                .iter()
+                .map(|path| format!("[[rows.glyphs]]\npath = \"{path}\"\n"));
+            fs::write(
+                dir.join("index.toml"),
+                format!("[[rows]]\n{}", glyphs.collect::<String>()),
+            )
+            .unwrap()
+        };
+        let changed = |paths: &[&str]| paths.iter().map(|path| path.to_string()).collect();
+
+        save("a.png", 1);
+        save("b.png", 2);
+        manifest(&["a.png", "b.png"]);
+        let mut watched = Watched::read(dir_str).unwrap();
+        assert_eq!(watched.images.len(), 2);
+
+        /* saved without changing, and a file that isn't a glyph */
+        save("a.png", 1);
+        manifest(&["a.png", "b.png"]);
+        let changes = watched
+            .reload(dir_str, &changed(&["a.png", "index.toml", "x.png"]))
+            .unwrap();
+        assert!(changes.is_empty());
+
+        save("b.png", 3);
+        save("c.png", 4);
+        manifest(&["b.png", "c.png"]);
+        let changes = watched
+            .reload(dir_str, &changed(&["b.png", "c.png", "index.toml"]))
+            .unwrap();
+        assert_eq!(
+            changes.to_string(),
+            "index.toml changed; changed b.png; added c.png; removed a.png"
+        );
+        assert_eq!(watched.loaded().unwrap().len(), 2);
+
+        /* a half written image leaves everything as it was */
+        fs::write(dir.join("c.png"), b"not a png").unwrap();
+        assert!(watched.reload(dir_str, &changed(&["c.png"])).is_err());
+        assert_eq!(watched.images["c.png"].get_pixel(0, 0)[0], 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
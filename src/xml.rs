@@ -0,0 +1,265 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::check::Problem;
+use crate::index::Index;
+use crate::layout::{Category, GameId, InfoCounts, BTS_COUNTS, BTS_ICON_ROWS, BTS_IDS};
+use crate::Error;
+
+/// An info in the XML with a `<FontButtonIndex>`, like a `<BonusInfo>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IconRef {
+    pub file: PathBuf,
+    /// the element it's in, like `BonusInfo`
+    pub info: String,
+    /// the `<Type>` of the info, like `BONUS_ALUMINUM`
+    pub ty: String,
+    pub font_button_index: u32,
+}
+
+/// What a mod's XML says about the icons in GameFont.tga. Counts are `None` if no file in the mod
+/// defines any of that kind of info, in which case the game uses the ones from Beyond the Sword.
+#[derive(Debug, Clone, Default)]
+pub struct XmlIcons {
+    pub religions: Option<usize>,
+    pub corporations: Option<usize>,
+    pub bonuses: Option<usize>,
+    pub refs: Vec<IconRef>,
+    /// `<FontButtonIndex>`es that aren't numbers, which are left out of `refs`
+    pub malformed: Vec<Problem>,
+}
+
+impl XmlIcons {
+    /// Read every .xml file under `dir`, like a mod's Assets directory.
+    pub fn scan<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        let mut files = Vec::new();
+        find_xml_files(dir.as_ref(), &mut files)?;
+        files.sort();
+
+        let mut icons = XmlIcons::default();
+        for file in files {
+            let bytes = fs::read(&file).map_err(|source| Error::Io {
+                action: "read",
+                path: file.clone(),
+                source,
+            })?;
+            /* the game's files say they're ISO-8859-1 but markup is all ascii */
+            let text = String::from_utf8_lossy(&bytes);
+            let doc = roxmltree::Document::parse(&text).map_err(|source| Error::Xml {
+                path: file.clone(),
+                source,
+            })?;
+            icons.add_document(&file, &doc);
+        }
+        Ok(icons)
+    }
+
+    fn add_document(&mut self, file: &Path, doc: &roxmltree::Document) {
+        for node in doc.descendants().filter(|node| node.is_element()) {
+            let count = match node.tag_name().name() {
+                "ReligionInfo" => Some(&mut self.religions),
+                "CorporationInfo" => Some(&mut self.corporations),
+                "BonusInfo" => Some(&mut self.bonuses),
+                _ => None,
+            };
+            if let Some(count) = count {
+                *count.get_or_insert(0) += 1;
+            }
+
+            let Some(index) = child_text(node, "FontButtonIndex") else {
+                continue;
+            };
+            let info = node.tag_name().name().to_owned();
+            let ty = child_text(node, "Type").unwrap_or("?").to_owned();
+            match index.trim().parse() {
+                Ok(font_button_index) => self.refs.push(IconRef {
+                    file: file.to_owned(),
+                    info,
+                    ty,
+                    font_button_index,
+                }),
+                Err(err) => self.malformed.push(Problem::error(format!(
+                    "{ty} in {}: {info} has FontButtonIndex {index:?}, which isn't a number: {err}",
+                    file.display()
+                ))),
+            }
+        }
+    }
+
+    /// Counts from the XML, falling back to Beyond the Sword's for kinds the mod doesn't define.
+    pub fn counts(&self) -> InfoCounts {
+        InfoCounts {
+            religions: self.religions.unwrap_or(BTS_COUNTS.religions),
+            corporations: self.corporations.unwrap_or(BTS_COUNTS.corporations),
+            bonuses: self.bonuses.unwrap_or(BTS_COUNTS.bonuses),
+        }
+    }
+
+    /// Compare the XML to the icon rows of `index`, which are assumed to be in the order of
+    /// `BTS_ICON_ROWS`.
+    pub fn check(&self, index: &Index) -> Vec<Problem> {
+        let mut problems = self.malformed.clone();
+        let counts = self.counts();
+        let ids = BTS_IDS.assign(index);
+        let icon_rows = index
+            .rows
+            .iter()
+            .zip(&ids)
+            .filter(|(_, ids)| matches!(ids.first(), Some(GameId::Icon(_))))
+            .collect::<Vec<_>>();
+        let expected = BTS_IDS.expected_row_starts(&counts);
+
+        for (i, (stock, &start)) in BTS_ICON_ROWS.iter().zip(&expected).enumerate() {
+            let name = stock.category.as_str();
+            let Some((row, row_ids)) = icon_rows.get(i) else {
+                problems.push(Problem::error(format!(
+                    "no {name} row, the atlas only has {} icon rows",
+                    icon_rows.len()
+                )));
+                continue;
+            };
+            if row_ids[0] != GameId::Icon(start) {
+                problems.push(Problem::error(format!(
+                    "{name} row starts at {} but the game numbers {name} icons from {start} \
+                     with {} religions, {} corporations, and {} bonuses",
+                    row_ids[0], counts.religions, counts.corporations, counts.bonuses,
+                )));
+            }
+
+            let needed = match stock.category {
+                Category::Religion => 2 * (counts.religions + counts.corporations),
+                Category::Bonus => 1 + counts.bonuses,
+                _ => continue,
+            };
+            if row.len() < needed {
+                problems.push(Problem::error(format!(
+                    "{name} row has {} glyphs but the XML needs {needed}",
+                    row.len()
+                )));
+            }
+        }
+
+        let bonus_row = BTS_ICON_ROWS
+            .iter()
+            .position(|stock| stock.category == Category::Bonus)
+            .and_then(|i| icon_rows.get(i))
+            .map_or(0, |(row, _)| row.len());
+        let mut seen = Vec::<&IconRef>::new();
+        for r in &self.refs {
+            let at = format!("{} in {}", r.ty, r.file.display());
+            if r.info != "BonusInfo" {
+                problems.push(Problem::warning(format!(
+                    "{at}: FontButtonIndex on a {}, only bonuses are checked",
+                    r.info
+                )));
+                continue;
+            }
+            if r.font_button_index as usize >= bonus_row {
+                problems.push(Problem::error(format!(
+                    "{at}: FontButtonIndex {} is past the end of the bonus row ({bonus_row} glyphs)",
+                    r.font_button_index
+                )));
+            } else if r.font_button_index == 0 {
+                problems.push(Problem::warning(format!(
+                    "{at}: FontButtonIndex 0 is the generic resource icon"
+                )));
+            }
+            if let Some(other) = seen
+                .iter()
+                .find(|other| other.font_button_index == r.font_button_index)
+            {
+                problems.push(Problem::warning(format!(
+                    "{at}: FontButtonIndex {} is also used by {}",
+                    r.font_button_index, other.ty
+                )));
+            }
+            seen.push(r);
+        }
+
+        problems
+    }
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.tag_name().name() == name)?
+        .text()
+}
+
+fn find_xml_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let read_error = |source| Error::Io {
+        action: "read",
+        path: dir.to_owned(),
+        source,
+    };
+    for entry in fs::read_dir(dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        if path.is_dir() {
+            find_xml_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("xml"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::Severity;
+    use crate::index::IndexGlyph;
+    use crate::layout::text_row_lengths;
+
+    #[test]
+    fn test_check_bonus_past_end_of_row() {
+        let xml = r#"<?xml version="1.0" encoding="ISO-8859-1"?>
+<Civ4BonusInfos xmlns="x-schema:CIV4TerrainSchema.xml">
+  <BonusInfos>
+    <BonusInfo>
+      <Type>BONUS_ALUMINUM</Type>
+      <FontButtonIndex>1</FontButtonIndex>
+    </BonusInfo>
+    <BonusInfo>
+      <Type>BONUS_TOFU</Type>
+      <FontButtonIndex>36</FontButtonIndex>
+    </BonusInfo>
+    <BonusInfo>
+      <Type>BONUS_TYPO</Type>
+      <FontButtonIndex>1O</FontButtonIndex>
+    </BonusInfo>
+  </BonusInfos>
+</Civ4BonusInfos>"#;
+        let mut icons = XmlIcons::default();
+        icons.add_document(
+            Path::new("CIV4BonusInfos.xml"),
+            &roxmltree::Document::parse(xml).unwrap(),
+        );
+        assert_eq!(icons.bonuses, Some(3));
+        assert_eq!(icons.refs.len(), 2);
+        assert_eq!(icons.refs[1].ty, "BONUS_TOFU");
+        assert_eq!(icons.malformed.len(), 1);
+        assert!(icons.malformed[0].message.contains("BONUS_TYPO"));
+        assert!(icons.malformed[0].message.contains("CIV4BonusInfos.xml"));
+
+        let mut lengths = text_row_lengths();
+        lengths.extend(BTS_ICON_ROWS.iter().map(|row| row.glyphs.len()));
+        let index = Index {
+            rows: lengths
+                .iter()
+                .map(|&len| vec![IndexGlyph::default(); len])
+                .collect(),
+            size: None,
+        };
+
+        /* as if the mod had all of the stock bonuses plus tofu */
+        icons.bonuses = None;
+        let problems = icons.check(&index);
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems.iter().all(|p| p.severity == Severity::Error));
+        assert!(problems[0].message.contains("BONUS_TYPO"));
+        assert!(problems[1].message.contains("BONUS_TOFU"));
+    }
+}